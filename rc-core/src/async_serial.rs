@@ -0,0 +1,364 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    task::JoinHandle,
+};
+
+use crate::serial::SerialConfig;
+use crate::transport::{self, Capabilities, ConnectionType, LineErrors, ModemStatus, Transport};
+
+/// A port driven by the tokio runtime instead of `Serial`'s worker threads.
+///
+/// Serial ports on Unix and `tcp://` connections are registered with the
+/// reactor, so an idle port costs no thread and no polling. Other transports
+/// fall back to reads on tokio's blocking pool. The far end closing or a
+/// device hanging up reads as end of file.
+///
+/// DTR/RTS from the config are applied on open; pacing is not, since callers
+/// decide themselves when to write.
+pub struct AsyncSerial {
+    inner: Inner,
+}
+
+enum Inner {
+    #[cfg(unix)]
+    Port(tokio::io::unix::AsyncFd<transport::SerialPortTransport>),
+    Tcp(TcpStream),
+    Blocking(Blocking),
+}
+
+impl AsyncSerial {
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Opens `device` the same way `Serial::start` does, including match
+    /// rules and `scheme://` devices.
+    pub async fn open(device: &str, config: &SerialConfig) -> io::Result<Self> {
+        let inner = match ConnectionType::parse(device) {
+            #[cfg(unix)]
+            (ConnectionType::Serial, name) => {
+                let (name, config) = (name.to_string(), config.clone());
+
+                let port = blocking(move || {
+                    transport::SerialPortTransport::open(&crate::port_match::resolve_device(&name)?, &config)
+                }).await?;
+
+                Inner::Port(port::register(port)?)
+            },
+            (ConnectionType::Tcp, address) => {
+                let stream = tokio::time::timeout(Self::CONNECT_TIMEOUT, TcpStream::connect(address))
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("connecting to {address} timed out")))??;
+
+                stream.set_nodelay(true)?;
+                Inner::Tcp(stream)
+            },
+            _ => {
+                let (device, config) = (device.to_string(), config.clone());
+                Inner::Blocking(Blocking::new(blocking(move || transport::open(&device, &config)).await?.into()))
+            },
+        };
+
+        let serial = Self { inner };
+
+        if let Some(dtr) = config.dtr {
+            serial.set_dtr(dtr)?;
+        }
+
+        if let Some(rts) = config.rts {
+            serial.set_rts(rts)?;
+        }
+
+        // Only the driver's RS-485 mode: without a transmit thread there is
+        // nobody to toggle RTS or remove the echo. Switched off explicitly
+        // otherwise, since the driver keeps it across close and open.
+        match serial.with_transport("RS-485 mode", |transport| transport.set_rs485(&config.rs485)) {
+            Err(e) if config.rs485.enabled || e.kind() != io::ErrorKind::Unsupported => return Err(e),
+            _ => (),
+        }
+
+        Ok(serial)
+    }
+
+    fn transport(&self) -> Option<&dyn Transport> {
+        match &self.inner {
+            #[cfg(unix)]
+            Inner::Port(port) => Some(port.get_ref()),
+            Inner::Tcp(_) => None,
+            Inner::Blocking(blocking) => Some(blocking.transport.as_ref()),
+        }
+    }
+
+    fn with_transport<T>(&self, operation: &str, f: impl FnOnce(&dyn Transport) -> io::Result<T>) -> io::Result<T> {
+        f(self.transport().ok_or_else(|| transport::unsupported(operation))?)
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.transport().map(|transport| transport.capabilities()).unwrap_or_default()
+    }
+
+    pub fn configure(&self, config: &SerialConfig) -> io::Result<()> {
+        self.with_transport("configure", |transport| transport.configure(config))
+    }
+
+    /// Line settings the driver actually applied.
+    pub fn effective_config(&self) -> io::Result<SerialConfig> {
+        self.with_transport("reading back settings", |transport| transport.settings())
+    }
+
+    pub fn set_dtr(&self, state: bool) -> io::Result<()> {
+        self.with_transport("DTR control", |transport| transport.set_dtr(state))
+    }
+
+    pub fn set_rts(&self, state: bool) -> io::Result<()> {
+        self.with_transport("RTS control", |transport| transport.set_rts(state))
+    }
+
+    pub fn set_break(&self, state: bool) -> io::Result<()> {
+        self.with_transport("BREAK", |transport| transport.set_break(state))
+    }
+
+    /// Asserts BREAK for `duration`.
+    pub async fn send_break(&self, duration: Duration) -> io::Result<()> {
+        self.set_break(true)?;
+        tokio::time::sleep(duration).await;
+        self.set_break(false)
+    }
+
+    pub fn modem_status(&self) -> io::Result<ModemStatus> {
+        self.with_transport("modem status", |transport| transport.modem_status())
+    }
+
+    /// Receive errors as counted by the driver, which keeps counting across
+    /// opens; use `LineErrors::since` on an earlier reading.
+    pub fn line_errors(&self) -> io::Result<LineErrors> {
+        self.with_transport("error counters", |transport| transport.line_errors())
+    }
+}
+
+impl AsyncRead for AsyncSerial {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().inner {
+            #[cfg(unix)]
+            Inner::Port(port) => port::poll_read(port, cx, buf),
+            Inner::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Inner::Blocking(blocking) => blocking.poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AsyncSerial {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().inner {
+            #[cfg(unix)]
+            Inner::Port(port) => port::poll_write(port, cx, buf),
+            Inner::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Inner::Blocking(blocking) => blocking.poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().inner {
+            #[cfg(unix)]
+            Inner::Port(_) => Poll::Ready(Ok(())),
+            Inner::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Inner::Blocking(blocking) => blocking.poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().inner {
+            #[cfg(unix)]
+            Inner::Port(_) => Poll::Ready(Ok(())),
+            Inner::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Inner::Blocking(blocking) => {
+                ready!(blocking.poll_flush(cx))?;
+                blocking.closed.store(true, Ordering::Relaxed);
+                Poll::Ready(blocking.transport.close())
+            },
+        }
+    }
+}
+
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> io::Result<T> + Send + 'static) -> io::Result<T> {
+    tokio::task::spawn_blocking(f).await.map_err(io::Error::other)?
+}
+
+#[cfg(unix)]
+mod port {
+    use std::{io, os::unix::io::{AsRawFd, RawFd}, task::{ready, Context, Poll}};
+    use tokio::io::{unix::AsyncFd, ReadBuf};
+
+    use crate::transport::SerialPortTransport;
+
+    pub fn register(port: SerialPortTransport) -> io::Result<AsyncFd<SerialPortTransport>> {
+        let fd = port.as_raw_fd();
+
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+
+            if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        AsyncFd::new(port)
+    }
+
+    fn check(result: isize) -> io::Result<usize> {
+        match result {
+            -1 => Err(io::Error::last_os_error()),
+            bytes => Ok(bytes as usize),
+        }
+    }
+
+    fn read(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+        check(unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) })
+    }
+
+    fn write(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+        check(unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) })
+    }
+
+    pub fn poll_read(port: &AsyncFd<SerialPortTransport>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(port.poll_read_ready(cx))?;
+
+            if let Ok(result) = guard.try_io(|port| read(port.as_raw_fd(), buf.initialize_unfilled())) {
+                let read = result?;
+                let unmarked = port.get_ref().unmark(buf.initialize_unfilled(), read);
+
+                // Only the start of an error mark, the rest is still to come.
+                if read > 0 && unmarked == 0 {
+                    continue;
+                }
+
+                buf.advance(unmarked);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    pub fn poll_write(port: &AsyncFd<SerialPortTransport>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(port.poll_write_ready(cx))?;
+
+            if let Ok(result) = guard.try_io(|port| write(port.as_raw_fd(), buf)) {
+                return Poll::Ready(result);
+            }
+        }
+    }
+}
+
+/// Adapts a `Transport` with blocking reads and writes, one operation at a
+/// time on tokio's blocking pool.
+struct Blocking {
+    transport: Arc<dyn Transport>,
+    closed: Arc<AtomicBool>,
+    unread: Vec<u8>,
+    read: Option<JoinHandle<io::Result<Vec<u8>>>>,
+    write: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Blocking {
+    const READ_SIZE: usize = 4096;
+
+    fn new(transport: Arc<dyn Transport>) -> Self {
+        Self {
+            transport,
+            closed: Arc::new(AtomicBool::new(false)),
+            unread: Vec::new(),
+            read: None,
+            write: None,
+        }
+    }
+
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.unread.is_empty() {
+            let transport = self.transport.clone();
+            let closed = self.closed.clone();
+
+            let read = self.read.get_or_insert_with(|| tokio::task::spawn_blocking(move || {
+                let mut data = vec![0u8; Self::READ_SIZE];
+
+                while !closed.load(Ordering::Relaxed) {
+                    match transport.read(&mut data) {
+                        Ok(read_bytes) => {
+                            data.truncate(read_bytes);
+                            return Ok(data);
+                        },
+                        Err(ref e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => (),
+                        Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                Ok(Vec::new())
+            }));
+
+            let result = ready!(Pin::new(read).poll(cx));
+            self.read = None;
+            self.unread = result.map_err(io::Error::other)??;
+        }
+
+        let read_bytes = self.unread.len().min(buf.remaining());
+        buf.put_slice(&self.unread[..read_bytes]);
+        self.unread.drain(..read_bytes);
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Takes the data right away and writes it in the background, like
+    /// `tokio::fs::File`; an error shows up on the next write or flush.
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ready!(self.poll_flush(cx))?;
+
+        let transport = self.transport.clone();
+        let closed = self.closed.clone();
+        let data = buf.to_vec();
+
+        self.write = Some(tokio::task::spawn_blocking(move || {
+            let mut written = 0;
+
+            while written < data.len() {
+                if closed.load(Ordering::Relaxed) {
+                    return Err(io::ErrorKind::BrokenPipe.into());
+                }
+
+                match transport.write(&data[written..]) {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(bytes) => written += bytes,
+                    Err(ref e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(())
+        }));
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(write) = self.write.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+
+        let result = ready!(Pin::new(write).poll(cx));
+        self.write = None;
+        Poll::Ready(result.map_err(io::Error::other)?)
+    }
+}
+
+impl Drop for Blocking {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use serial2::CharSize;
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::serial::{Parity, SerialConfig};
+use crate::transport;
+
+/// What to try while looking for the settings a device talks with.
+#[derive(Debug, Clone)]
+pub struct AutobaudOptions {
+    pub baudrates: Vec<u32>,
+    /// Also try 7E1, 7O1, 8E1 and 8O1 at every rate, not only 8N1.
+    pub framings: bool,
+    /// How long to listen at each candidate.
+    pub sample_time: Duration,
+    /// Sent right after opening, e.g. `\r` to make a console print its prompt.
+    pub probe: Vec<u8>,
+}
+
+impl Default for AutobaudOptions {
+    fn default() -> Self {
+        Self {
+            baudrates: vec![1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600],
+            framings: false,
+            sample_time: Duration::from_millis(500),
+            probe: Vec::new(),
+        }
+    }
+}
+
+/// One tried configuration and how plausible the data received with it looked.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub config: SerialConfig,
+    /// From 0 (nothing, or garbage) to 1 (plenty of clean text).
+    pub score: f32,
+    pub sample: Vec<u8>,
+}
+
+const FRAMINGS: [(CharSize, Parity); 5] = [
+    (CharSize::Bits8, Parity::None),
+    (CharSize::Bits7, Parity::Even),
+    (CharSize::Bits7, Parity::Odd),
+    (CharSize::Bits8, Parity::Even),
+    (CharSize::Bits8, Parity::Odd),
+];
+
+/// Samples that are shorter than this count as less certain.
+const CONFIDENT_SAMPLE_LEN: usize = 32;
+
+/// Rates how much `sample` looks like text received with matching settings.
+///
+/// A wrong rate shows up as bytes outside printable ASCII, and framing
+/// errors are typically reported as NUL bytes, so both lower the score.
+pub fn score(sample: &[u8]) -> f32 {
+    if sample.is_empty() {
+        return 0.0;
+    }
+
+    let plausible = sample
+        .iter()
+        .filter(|&&byte| matches!(byte, b'\t' | b'\n' | b'\r' | 0x20..=0x7e))
+        .count();
+
+    let ratio = plausible as f32 / sample.len() as f32;
+    let confidence = sample.len().min(CONFIDENT_SAMPLE_LEN) as f32 / CONFIDENT_SAMPLE_LEN as f32;
+
+    ratio * confidence
+}
+
+/// Opens `device` once per candidate configuration, listens for a while and
+/// returns the candidates best first. Other settings, such as flow control,
+/// are taken from `config`.
+pub fn scan(device: &str, config: &SerialConfig, options: &AutobaudOptions) -> Result<Vec<Candidate>> {
+    let framings = if options.framings { &FRAMINGS[..] } else { &FRAMINGS[..1] };
+    let mut candidates = Vec::new();
+
+    for &baudrate in &options.baudrates {
+        for &(char_size, parity) in framings {
+            let config = SerialConfig {
+                baudrate,
+                char_size,
+                parity,
+                ..config.clone()
+            };
+
+            match sample(device, &config, options) {
+                Ok(sample) => candidates.push(Candidate {
+                    score: score(&sample),
+                    config,
+                    sample,
+                }),
+                // The driver rejected this rate; the others may still work.
+                Err(e) if e.kind() == io::ErrorKind::InvalidInput => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // The sort is stable, so ties keep the order above and 8N1 wins.
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(candidates)
+}
+
+fn sample(device: &str, config: &SerialConfig, options: &AutobaudOptions) -> io::Result<Vec<u8>> {
+    let transport = transport::open(device, config)?;
+
+    if !options.probe.is_empty() {
+        transport.write(&options.probe)?;
+    }
+
+    let mut sample = Vec::new();
+    let mut buf = [0u8; 1000];
+    let deadline = Instant::now() + options.sample_time;
+
+    while Instant::now() < deadline {
+        match transport.read(&mut buf) {
+            Ok(read_bytes) => sample.extend_from_slice(&buf[..read_bytes]),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    transport.close()?;
+    Ok(sample)
+}
+
+/// Scans `device` and prints the ranking.
+pub fn run(device: &str, config: SerialConfig, options: AutobaudOptions) -> Result<()> {
+    println!("Scanning {device}...");
+
+    let candidates = scan(device, &config, &options)?;
+
+    println!("{:>4}  {:>8}  {:<6}  {:>5}  {:>5}  Sample", "Rank", "Baud", "Frame", "Score", "Bytes");
+
+    for (rank, candidate) in candidates.iter().enumerate() {
+        let sample: String = String::from_utf8_lossy(&candidate.sample).escape_debug().take(40).collect();
+
+        println!(
+            "{:>4}  {:>8}  {:<6}  {:>5.2}  {:>5}  {sample}",
+            rank + 1,
+            candidate.config.baudrate,
+            candidate.config.framing(),
+            candidate.score,
+            candidate.sample.len(),
+        );
+    }
+
+    match candidates.first() {
+        Some(best) if best.score > 0.0 => println!("Best match: {} {}", best.config.baudrate, best.config.framing()),
+        _ => println!("No data received; try --probe or a longer --sample-ms"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,452 @@
+use anyhow::Result;
+use flume::{bounded, unbounded, Receiver, Sender};
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::rfc2217::{self, command, control, Event, Telnet};
+use crate::serial::{Serial, SerialConfig, SerialEvent};
+use crate::transport::ModemStatus;
+
+/// Something a bridge client asked for, to be applied to the local port.
+#[derive(Debug, Clone)]
+pub enum BridgeEvent {
+    ClientConnected(SocketAddr),
+    ClientDisconnected(SocketAddr),
+    /// Accepting a client failed; the bridge keeps listening.
+    Error(String),
+    Data(Vec<u8>),
+    Configure(SerialConfig),
+    SetDtr(bool),
+    SetRts(bool),
+    SetBreak(bool),
+}
+
+struct Client {
+    address: SocketAddr,
+    stream: TcpStream,
+    /// Feeds the client's writer thread, so one that stops reading holds up
+    /// nobody but itself.
+    output: Sender<Vec<u8>>,
+}
+
+impl Client {
+    /// Queues `data` for the client, or disconnects it if it fell too far behind.
+    fn send(&self, data: &[u8]) -> io::Result<()> {
+        if self.output.try_send(data.to_vec()).is_err() {
+            self.stream.shutdown(Shutdown::Both).ok();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("{} is not keeping up", self.address)));
+        }
+
+        Ok(())
+    }
+}
+
+struct Shared {
+    rfc2217: bool,
+    running: AtomicBool,
+    clients: Mutex<Vec<Arc<Client>>>,
+    config: Mutex<SerialConfig>,
+    /// Control lines as clients last set them, to answer their requests.
+    break_state: AtomicBool,
+    dtr: AtomicBool,
+    rts: AtomicBool,
+    events: Sender<BridgeEvent>,
+}
+
+/// Exposes a local port on a TCP listen socket, as raw TCP or RFC 2217.
+///
+/// The bridge does not own the port: data received from the port is passed
+/// to `broadcast`, and whatever clients send comes back from `try_recv`.
+pub struct Bridge {
+    shared: Arc<Shared>,
+    events: Receiver<BridgeEvent>,
+    local_addr: SocketAddr,
+}
+
+impl Bridge {
+    const OPTIONS: [u8; 3] = [rfc2217::BINARY, rfc2217::SUPPRESS_GO_AHEAD, rfc2217::COM_PORT_OPTION];
+    const SIGNATURE: &'static [u8] = b"rustcom";
+    /// Writes queued for a client before it is dropped as not keeping up.
+    const CLIENT_QUEUE: usize = 1024;
+    /// A client that accepts nothing for this long is dropped.
+    const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn start(listen_address: &str, config: SerialConfig, rfc2217: bool) -> Result<Self> {
+        let listener = TcpListener::bind(listen_address)?;
+        listener.set_nonblocking(true)?;
+
+        let (sender, receiver) = unbounded();
+
+        let shared = Arc::new(Shared {
+            rfc2217,
+            running: AtomicBool::new(true),
+            clients: Mutex::new(Vec::new()),
+            break_state: AtomicBool::new(false),
+            dtr: AtomicBool::new(config.dtr.unwrap_or(true)),
+            rts: AtomicBool::new(config.rts.unwrap_or(true)),
+            config: Mutex::new(config),
+            events: sender,
+        });
+
+        let bridge = Self {
+            shared: shared.clone(),
+            events: receiver,
+            local_addr: listener.local_addr()?,
+        };
+
+        thread::spawn(move || {
+            while shared.running.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, address)) => {
+                        if let Err(e) = Self::accept(&shared, stream, address) {
+                            shared.events.send(BridgeEvent::Error(format!("{address}: {e}"))).ok();
+                        }
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+                    Err(e) => {
+                        shared.events.send(BridgeEvent::Error(e.to_string())).ok();
+                        // Such as running out of file descriptors, which takes a while to clear.
+                        thread::sleep(Duration::from_millis(50));
+                    },
+                }
+            }
+        });
+
+        Ok(bridge)
+    }
+
+    pub fn stop(&self) {
+        self.shared.running.store(false, Ordering::Relaxed);
+
+        for client in self.shared.clients.lock().unwrap().drain(..) {
+            client.stream.shutdown(Shutdown::Both).ok();
+        }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn is_rfc2217(&self) -> bool {
+        self.shared.rfc2217
+    }
+
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        self.shared.clients.lock().unwrap().iter().map(|client| client.address).collect()
+    }
+
+    /// Taken out of the lock, so sending never holds it. Clients that fail
+    /// are shut down, and their reader threads remove them.
+    fn send_to_all(&self, data: &[u8]) {
+        let clients = self.shared.clients.lock().unwrap().clone();
+
+        for client in clients {
+            client.send(data).ok();
+        }
+    }
+
+    /// Sends data received from the local port to every client.
+    pub fn broadcast(&self, data: &[u8]) {
+        let data = if self.shared.rfc2217 {
+            rfc2217::escape(data)
+        } else {
+            data.to_vec()
+        };
+
+        self.send_to_all(&data);
+    }
+
+    /// Tells RFC 2217 clients about a change of the local port's input lines.
+    pub fn notify_modem_status(&self, status: ModemStatus) {
+        if self.shared.rfc2217 {
+            let value = rfc2217::modem_state_value(status);
+            let frame = rfc2217::com_port_command(command::NOTIFY_MODEMSTATE + command::SERVER_OFFSET, &[value]);
+
+            self.send_to_all(&frame);
+        }
+    }
+
+    /// Keeps the settings reported to RFC 2217 clients in sync with the local port.
+    pub fn set_config(&self, config: SerialConfig) {
+        *self.shared.config.lock().unwrap() = config;
+    }
+
+    pub fn try_recv(&self) -> Option<BridgeEvent> {
+        self.events.try_recv().ok()
+    }
+
+    fn accept(shared: &Arc<Shared>, stream: TcpStream, address: SocketAddr) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+        stream.set_write_timeout(Some(Self::WRITE_TIMEOUT))?;
+
+        let mut writer = stream.try_clone()?;
+        let (output, queue) = bounded::<Vec<u8>>(Self::CLIENT_QUEUE);
+
+        // Ends when the client is dropped, or disconnects it when writing fails.
+        thread::spawn(move || {
+            for data in queue.iter() {
+                if writer.write_all(&data).is_err() {
+                    writer.shutdown(Shutdown::Both).ok();
+                    break;
+                }
+            }
+        });
+
+        let client = Arc::new(Client {
+            address,
+            stream,
+            output,
+        });
+
+        if shared.rfc2217 {
+            let mut negotiation = Vec::new();
+            for option in Self::OPTIONS {
+                negotiation.extend(rfc2217::negotiation(rfc2217::WILL, option));
+                negotiation.extend(rfc2217::negotiation(rfc2217::DO, option));
+            }
+            client.send(&negotiation)?;
+        }
+
+        shared.clients.lock().unwrap().push(client.clone());
+        shared.events.send(BridgeEvent::ClientConnected(address)).ok();
+
+        let shared = shared.clone();
+
+        thread::spawn(move || {
+            let mut telnet = Telnet::new();
+            let mut buf = [0u8; 1000];
+
+            while shared.running.load(Ordering::Relaxed) {
+                match (&client.stream).read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(read_bytes) if shared.rfc2217 => {
+                        let mut data = Vec::new();
+                        let mut events = Vec::new();
+                        telnet.decode(&buf[..read_bytes], &mut data, &mut events);
+
+                        if events.into_iter().any(|event| Self::handle_event(&shared, &client, event).is_err()) {
+                            break;
+                        }
+
+                        if !data.is_empty() {
+                            shared.events.send(BridgeEvent::Data(data)).ok();
+                        }
+                    },
+                    Ok(read_bytes) => {
+                        shared.events.send(BridgeEvent::Data(buf[..read_bytes].to_vec())).ok();
+                    },
+                    Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
+                    Err(_) => break,
+                }
+            }
+
+            shared.clients.lock().unwrap().retain(|other| !Arc::ptr_eq(other, &client));
+            shared.events.send(BridgeEvent::ClientDisconnected(client.address)).ok();
+        });
+
+        Ok(())
+    }
+
+    fn handle_event(shared: &Shared, client: &Client, event: Event) -> io::Result<()> {
+        match event {
+            Event::Do(option) if !Self::OPTIONS.contains(&option) => {
+                client.send(&rfc2217::negotiation(rfc2217::WONT, option))
+            },
+            Event::Will(option) if !Self::OPTIONS.contains(&option) => {
+                client.send(&rfc2217::negotiation(rfc2217::DONT, option))
+            },
+            Event::Subnegotiation(rfc2217::COM_PORT_OPTION, payload) => {
+                match payload.split_first() {
+                    Some((&code, value)) => {
+                        let response = Self::handle_command(shared, code, value);
+                        client.send(&rfc2217::com_port_command(code.wrapping_add(command::SERVER_OFFSET), &response))
+                    },
+                    None => Ok(()),
+                }
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies a COM-PORT-OPTION command and returns the value to acknowledge.
+    /// A zero value is a query and leaves the setting unchanged.
+    fn handle_command(shared: &Shared, code: u8, value: &[u8]) -> Vec<u8> {
+        let mut config = shared.config.lock().unwrap();
+        let previous = config.clone();
+
+        let response = match (code, value) {
+            (command::SIGNATURE, _) => Self::SIGNATURE.to_vec(),
+            (command::SET_BAUDRATE, &[a, b, c, d]) => {
+                let baudrate = u32::from_be_bytes([a, b, c, d]);
+
+                if baudrate != 0 {
+                    config.baudrate = baudrate;
+                }
+
+                config.baudrate.to_be_bytes().to_vec()
+            },
+            (command::SET_DATASIZE, &[value]) => {
+                if let Some(char_size) = rfc2217::char_size(value) {
+                    config.char_size = char_size;
+                }
+
+                vec![rfc2217::data_size(config.char_size)]
+            },
+            (command::SET_PARITY, &[value]) => {
+                if let Some(parity) = rfc2217::parity(value) {
+                    config.parity = parity;
+                }
+
+                vec![rfc2217::parity_value(config.parity)]
+            },
+            (command::SET_STOPSIZE, &[value]) => {
+                if let Some(stop_bits) = rfc2217::stop_bits(value) {
+                    config.stop_bits = stop_bits;
+                }
+
+                vec![rfc2217::stop_size(config.stop_bits)]
+            },
+            (command::SET_CONTROL, &[value]) => {
+                let event = match value {
+                    control::BREAK_ON | control::BREAK_OFF => {
+                        shared.break_state.store(value == control::BREAK_ON, Ordering::Relaxed);
+                        Some(BridgeEvent::SetBreak(value == control::BREAK_ON))
+                    },
+                    control::DTR_ON | control::DTR_OFF => {
+                        shared.dtr.store(value == control::DTR_ON, Ordering::Relaxed);
+                        Some(BridgeEvent::SetDtr(value == control::DTR_ON))
+                    },
+                    control::RTS_ON | control::RTS_OFF => {
+                        shared.rts.store(value == control::RTS_ON, Ordering::Relaxed);
+                        Some(BridgeEvent::SetRts(value == control::RTS_ON))
+                    },
+                    _ => {
+                        if let Some(flow_control) = rfc2217::flow_control(value) {
+                            config.flow_control = flow_control;
+                        }
+
+                        None
+                    },
+                };
+
+                if let Some(event) = event {
+                    shared.events.send(event).ok();
+                }
+
+                // Requests are answered with the current state.
+                let state = |line: &AtomicBool, on, off| match line.load(Ordering::Relaxed) {
+                    true => on,
+                    false => off,
+                };
+
+                match value {
+                    0 => vec![rfc2217::flow_control_value(config.flow_control)],
+                    control::BREAK_REQUEST => vec![state(&shared.break_state, control::BREAK_ON, control::BREAK_OFF)],
+                    control::DTR_REQUEST => vec![state(&shared.dtr, control::DTR_ON, control::DTR_OFF)],
+                    control::RTS_REQUEST => vec![state(&shared.rts, control::RTS_ON, control::RTS_OFF)],
+                    _ => vec![value],
+                }
+            },
+            _ => value.to_vec(),
+        };
+
+        if *config != previous {
+            shared.events.send(BridgeEvent::Configure(config.clone())).ok();
+        }
+
+        response
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Runs a headless bridge until the process is terminated. If the port goes
+/// away it is reopened as soon as it comes back, while clients stay connected.
+pub fn run(device: &str, config: SerialConfig, listen_address: &str, rfc2217: bool) -> Result<()> {
+    let serial = Serial::new();
+    serial.start(device, config.clone())?;
+
+    if let Ok(applied) = serial.effective_config() {
+        for mismatch in config.mismatches(&applied) {
+            eprintln!("warning: {device}: {mismatch}");
+        }
+    }
+
+    let bridge = Bridge::start(listen_address, config, rfc2217)?;
+    println!("Bridging {device} on {}{}", bridge.local_addr(), if rfc2217 { " (RFC 2217)" } else { "" });
+
+    loop {
+        let mut idle = true;
+
+        while let Some(data) = serial.try_recv() {
+            bridge.broadcast(&data);
+            idle = false;
+        }
+
+        while let Some(status) = serial.try_recv_modem_status() {
+            bridge.notify_modem_status(status);
+        }
+
+        while let Some(event) = serial.try_recv_event() {
+            match event {
+                SerialEvent::Connected(device) => println!("{device} connected"),
+                SerialEvent::Disconnected(None) => (),
+                SerialEvent::Disconnected(Some(error)) => {
+                    eprintln!("{device} disconnected: {error}");
+                    serial.stop().ok();
+
+                    while serial.reconnect().is_err() {
+                        thread::sleep(Serial::RECONNECT_INTERVAL);
+                    }
+                },
+                SerialEvent::ReadError(error) => eprintln!("{device}: read failed: {error}"),
+                SerialEvent::WriteError(error) => eprintln!("{device}: write failed: {error}"),
+                SerialEvent::BreakError(error) => eprintln!("{device}: BREAK failed: {error}"),
+                SerialEvent::Overrun(lost) => eprintln!("{device}: {lost} bytes lost to receive overruns"),
+            }
+        }
+
+        while let Some(event) = bridge.try_recv() {
+            idle = false;
+
+            let result = match event {
+                BridgeEvent::ClientConnected(address) => {
+                    println!("{address} connected");
+                    Ok(())
+                },
+                BridgeEvent::ClientDisconnected(address) => {
+                    println!("{address} disconnected");
+                    Ok(())
+                },
+                BridgeEvent::Error(error) => {
+                    eprintln!("bridge: {error}");
+                    Ok(())
+                },
+                BridgeEvent::Data(data) => serial.send(&data),
+                BridgeEvent::Configure(config) => serial.configure(&config),
+                BridgeEvent::SetDtr(state) => serial.set_dtr(state),
+                BridgeEvent::SetRts(state) => serial.set_rts(state),
+                BridgeEvent::SetBreak(state) => serial.set_break(state),
+            };
+
+            if let Err(e) = result {
+                eprintln!("{e:?}");
+            }
+        }
+
+        if idle {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
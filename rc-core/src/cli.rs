@@ -1,30 +1,78 @@
 use anyhow::Result;
-use clap::Parser;
-use serial2::{FlowControl, CharSize, Parity, StopBits};
+use clap::{Parser, Subcommand};
+use serial2::{FlowControl, CharSize, StopBits};
+use std::time::Duration;
 
-use crate::serial::SerialConfig;
+use crate::autobaud::AutobaudOptions;
+use crate::port_match::PortMatch;
+use crate::serial::{LineWait, Pacing, Parity, Rs485, Serial, SerialConfig};
+use crate::transport::ConnectionType;
 
 pub enum AppType {
     Tui,
     Gui,
+    Bridge {
+        listen: String,
+        rfc2217: bool,
+    },
+    Autobaud(AutobaudOptions),
+    List {
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Expose the serial device on a TCP listen socket")]
+    Bridge {
+        #[arg(short, long, default_value = "0.0.0.0:2217", help = "Address to listen on")]
+        listen: String,
+
+        #[arg(long, help = "Accept RFC 2217 clients instead of raw TCP")]
+        rfc2217: bool,
+    },
+
+    #[command(long_flag = "list", about = "List the serial ports of this system and what is known about them")]
+    List {
+        #[arg(long, help = "Print JSON for scripts")]
+        json: bool,
+    },
+
+    #[command(about = "Try common baud rates on the device and rank them by how plausible the received data looks")]
+    Autobaud {
+        #[arg(short, long, value_delimiter = ',', value_parser = possible_baudrate, help = "Comma separated rates to try instead of the common ones")]
+        rates: Vec<u32>,
+
+        #[arg(long, help = "Also try 7E1, 7O1, 8E1 and 8O1")]
+        framings: bool,
+
+        #[arg(long, default_value_t = 500, help = "Milliseconds to listen at each rate")]
+        sample_ms: u64,
+
+        #[arg(long, help = "Text to send after opening, e.g. '\\r'; \\r, \\n and \\t are unescaped")]
+        probe: Option<String>,
+    },
 }
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(long, help = "Disable gui and start rustcom as a TUI application")]
     no_gui: bool,
 
-    #[arg(short, long, help = "Serial device name")]
+    #[arg(short, long, value_parser = possible_device, help = "Serial device name, usb:VID:PID, serial:NUMBER, product:\"GLOB\", tcp://host:port, rfc2217://host:port, loop:// or mock://script.toml")]
     device: Option<String>,
 
-    #[arg(short, long, help = "115200 (default)")]
+    #[arg(short, long, value_parser = possible_baudrate, help = "Any rate the driver supports, e.g. 250000; 115200 (default)")]
     baudrate: Option<u32>,
 
     #[arg(short = 'c', long, value_parser = possible_char_size, help = "Possible values: 5, 6, 7, 8 (default)")]
     char_size: Option<CharSize>,
 
-    #[arg(short, long, value_parser = possible_parity, help = "Possible values: none (default), odd, even")]
+    #[arg(short, long, value_parser = possible_parity, help = "Possible values: none (default), odd, even, mark, space")]
     parity: Option<Parity>,
 
     #[arg(short, long, value_parser = possible_flow_control, help = "Possible values: none (default), xonxoff, rtscts")]
@@ -32,6 +80,73 @@ pub struct Cli {
 
     #[arg(short, long, value_parser = possible_stop_bits, help = "Possible values: 1 (default), 2")]
     stop_bits: Option<StopBits>,
+
+    #[arg(long, value_parser = possible_line_state, help = "DTR state on connect: on, off")]
+    dtr: Option<bool>,
+
+    #[arg(long, value_parser = possible_line_state, help = "RTS state on connect: on, off")]
+    rts: Option<bool>,
+
+    #[arg(long, value_name = "MS", help = "Delay after every character sent")]
+    char_delay_ms: Option<u64>,
+
+    #[arg(long, value_name = "MS", help = "Delay after every line sent")]
+    line_delay_ms: Option<u64>,
+
+    #[arg(long, conflicts_with = "wait_prompt", help = "Wait for every line to be echoed before sending the next")]
+    wait_echo: bool,
+
+    #[arg(long, value_name = "TEXT", help = "Wait for this prompt after every line before sending the next")]
+    wait_prompt: Option<String>,
+
+    #[arg(long, help = "Replace bytes received with parity or framing errors by SUB (0x1a)")]
+    mark_errors: bool,
+
+    #[arg(long, help = "Half-duplex RS-485: RTS enables the transmitter while sending")]
+    rs485: bool,
+
+    #[arg(long, requires = "rs485", help = "Drive RTS low instead of high while sending")]
+    rs485_rts_low: bool,
+
+    #[arg(long, value_name = "MS", requires = "rs485", help = "Delay between raising RTS and sending")]
+    rs485_delay_before_ms: Option<u64>,
+
+    #[arg(long, value_name = "MS", requires = "rs485", help = "Delay between the last byte sent and dropping RTS")]
+    rs485_delay_after_ms: Option<u64>,
+
+    #[arg(long, requires = "rs485", help = "Keep receiving while sending")]
+    rs485_rx_during_tx: bool,
+
+    #[arg(long, requires = "rs485", help = "Drop our own data echoed by the transceiver from what is received")]
+    remove_echo: bool,
+
+    #[arg(long, help = "Don't lock the port, so other programs can open it at the same time")]
+    no_lock: bool,
+}
+
+fn possible_device(s: &str) -> Result<String, String> {
+    match ConnectionType::parse(s) {
+        (connection_type @ (ConnectionType::Tcp | ConnectionType::Rfc2217), address) => match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(s.to_string()),
+            _ => Err(format!("Expected {}", connection_type.device(connection_type.address_hint()))),
+        },
+        (ConnectionType::Serial, name) => match PortMatch::parse(name) {
+            Some(Err(e)) => Err(e),
+            _ => Ok(s.to_string()),
+        },
+        _ => Ok(s.to_string()),
+    }
+}
+
+fn possible_baudrate(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(baudrate) if baudrate > 0 => Ok(baudrate),
+        _ => Err("Expected a positive number of bits per second, e.g. 115200".to_string())
+    }
+}
+
+fn unescape(s: &str) -> Vec<u8> {
+    s.replace("\\r", "\r").replace("\\n", "\n").replace("\\t", "\t").into_bytes()
 }
 
 fn possible_char_size(s: &str) -> Result<CharSize, String> {
@@ -49,7 +164,9 @@ fn possible_parity(s: &str) -> Result<Parity, String> {
         "none" => Ok(Parity::None),
         "even" => Ok(Parity::Even),
         "odd" => Ok(Parity::Odd),
-        _ => Err("Possible values: none (default), odd, even".to_string())
+        "mark" => Ok(Parity::Mark),
+        "space" => Ok(Parity::Space),
+        _ => Err("Possible values: none (default), odd, even, mark, space".to_string())
     }
 }
 
@@ -70,6 +187,14 @@ fn possible_stop_bits(s: &str) -> Result<StopBits, String> {
     }
 }
 
+fn possible_line_state(s: &str) -> Result<bool, String> {
+    match s {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err("Possible values: on, off".to_string())
+    }
+}
+
 pub fn run() -> Result<(AppType, String, SerialConfig)> {
     let cli = Cli::parse();
 
@@ -81,11 +206,85 @@ pub fn run() -> Result<(AppType, String, SerialConfig)> {
         parity: cli.parity.unwrap_or(Parity::None),
         flow_control: cli.flow_control.unwrap_or(FlowControl::None),
         stop_bits: cli.stop_bits.unwrap_or(StopBits::One),
+        dtr: cli.dtr,
+        rts: cli.rts,
+        pacing: Pacing {
+            char_delay: Duration::from_millis(cli.char_delay_ms.unwrap_or(0)),
+            line_delay: Duration::from_millis(cli.line_delay_ms.unwrap_or(0)),
+            line_wait: match cli.wait_prompt {
+                Some(prompt) => LineWait::Prompt(String::from_utf8_lossy(&unescape(&prompt)).into_owned()),
+                None if cli.wait_echo => LineWait::Echo,
+                None => LineWait::Nothing,
+            },
+        },
+        mark_errors: cli.mark_errors,
+        rs485: Rs485 {
+            enabled: cli.rs485,
+            rts_on_send: !cli.rs485_rts_low,
+            delay_before_send: Duration::from_millis(cli.rs485_delay_before_ms.unwrap_or(0)),
+            delay_after_send: Duration::from_millis(cli.rs485_delay_after_ms.unwrap_or(0)),
+            rx_during_tx: cli.rs485_rx_during_tx,
+            remove_echo: cli.remove_echo,
+        },
+        exclusive: !cli.no_lock,
     };
 
+    match cli.command {
+        Some(Command::Bridge { listen, rfc2217 }) => {
+            return Ok((AppType::Bridge { listen, rfc2217 }, device, config))
+        },
+        Some(Command::Autobaud { rates, framings, sample_ms, probe }) => {
+            let defaults = AutobaudOptions::default();
+
+            let options = AutobaudOptions {
+                baudrates: if rates.is_empty() { defaults.baudrates } else { rates },
+                framings,
+                sample_time: Duration::from_millis(sample_ms),
+                probe: probe.as_deref().map(unescape).unwrap_or_default(),
+            };
+
+            return Ok((AppType::Autobaud(options), device, config))
+        },
+        Some(Command::List { json }) => {
+            return Ok((AppType::List { json }, device, config))
+        },
+        None => (),
+    }
+
     if cli.no_gui {
         return Ok((AppType::Tui, device, config))
     }
 
     Ok((AppType::Gui, device, config))
+}
+
+/// Prints the ports of this system, as a table or as JSON.
+pub fn list_ports(json: bool) -> Result<()> {
+    let ports = Serial::available_ports()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&ports)?);
+        return Ok(())
+    }
+
+    println!("{:<20}  {:<9}  {:<9}  {:<16}  {:<20}  Product", "Name", "Type", "VID:PID", "Serial", "Manufacturer");
+
+    for port in ports {
+        let usb_id = match (port.vid, port.pid) {
+            (Some(vid), Some(pid)) => format!("{vid:04x}:{pid:04x}"),
+            _ => String::new(),
+        };
+
+        println!(
+            "{:<20}  {:<9}  {:<9}  {:<16}  {:<20}  {}",
+            port.name,
+            port.port_type,
+            usb_id,
+            port.serial_number.unwrap_or_default(),
+            port.manufacturer.unwrap_or_default(),
+            port.product.unwrap_or_default(),
+        );
+    }
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,185 @@
+use flume::{unbounded, Receiver, Sender};
+use std::{
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{port_lock::{self, Holder}, serial::{PortInfo, Serial}};
+
+#[derive(Debug, Clone)]
+pub enum HotplugEvent {
+    Added(PortInfo),
+    Removed(PortInfo),
+    /// The ports some process has open, sent whenever that changes. Ports
+    /// are opened and closed by other programs without any event to tell,
+    /// so this is checked on every poll.
+    Holders(Vec<(String, Holder)>),
+}
+
+/// Watches for serial ports being plugged in and removed.
+///
+/// On Linux the list is rescanned as soon as the kernel reports a tty
+/// device change; everywhere else, and as a fallback, it is polled.
+/// Which of them other processes hold is checked along with each poll.
+pub struct PortWatcher {
+    running: Arc<AtomicBool>,
+    events: Receiver<HotplugEvent>,
+}
+
+impl PortWatcher {
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
+    /// Gives the device node and its properties time to appear after the kernel event.
+    const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+    pub fn start() -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let (sender, receiver) = unbounded();
+
+        let watch_running = running.clone();
+
+        thread::spawn(move || {
+            let mut uevents = uevent::Socket::open().ok();
+            let mut ports = Serial::available_ports().unwrap_or_default();
+            let mut holders = Self::holders(&ports);
+            let mut scanned = Instant::now();
+
+            sender.send(HotplugEvent::Holders(holders.clone())).ok();
+
+            while watch_running.load(Ordering::Relaxed) {
+                let changed = match uevents.as_mut() {
+                    Some(socket) => socket.wait_for_tty(Duration::from_millis(250)),
+                    None => {
+                        thread::sleep(Duration::from_millis(250));
+                        false
+                    },
+                };
+
+                if changed {
+                    thread::sleep(Self::SETTLE_TIME);
+                } else if scanned.elapsed() < Self::POLL_INTERVAL {
+                    continue;
+                }
+
+                scanned = Instant::now();
+
+                if let Ok(current) = Serial::available_ports() {
+                    Self::diff(&ports, &current, &sender);
+                    ports = current;
+                }
+
+                let current = Self::holders(&ports);
+
+                if current != holders {
+                    sender.send(HotplugEvent::Holders(current.clone())).ok();
+                    holders = current;
+                }
+            }
+        });
+
+        Self {
+            running,
+            events: receiver,
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<HotplugEvent> {
+        self.events.try_recv().ok()
+    }
+
+    fn holders(ports: &[PortInfo]) -> Vec<(String, Holder)> {
+        let names: Vec<&str> = ports.iter().map(|port| port.name.as_str()).collect();
+        port_lock::holders(&names)
+    }
+
+    fn diff(previous: &[PortInfo], current: &[PortInfo], sender: &Sender<HotplugEvent>) {
+        for port in previous.iter().filter(|port| !current.iter().any(|other| other.name == port.name)) {
+            sender.send(HotplugEvent::Removed(port.clone())).ok();
+        }
+
+        for port in current.iter().filter(|port| !previous.iter().any(|other| other.name == port.name)) {
+            sender.send(HotplugEvent::Added(port.clone())).ok();
+        }
+    }
+}
+
+impl Drop for PortWatcher {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod uevent {
+    use std::{fs::File, io::{self, Read}, os::unix::io::{AsRawFd, FromRawFd}, time::Duration};
+
+    /// Kernel device events, the same ones udev listens to.
+    pub struct Socket(File);
+
+    impl Socket {
+        pub fn open() -> io::Result<Self> {
+            unsafe {
+                let fd = libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_KOBJECT_UEVENT);
+
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                let socket = Self(File::from_raw_fd(fd));
+
+                let mut address = std::mem::zeroed::<libc::sockaddr_nl>();
+                address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+                address.nl_groups = 1;
+
+                let bound = libc::bind(
+                    fd,
+                    &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                );
+
+                if bound != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(socket)
+            }
+        }
+
+        /// Waits up to `timeout` and tells whether a tty device was added or removed.
+        pub fn wait_for_tty(&mut self, timeout: Duration) -> bool {
+            let mut poll_fd = libc::pollfd {
+                fd: self.0.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            if unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) } <= 0 {
+                return false;
+            }
+
+            let mut buf = [0u8; 4096];
+
+            match self.0.read(&mut buf) {
+                Ok(read_bytes) => buf[..read_bytes].split(|&byte| byte == 0).any(|field| field == b"SUBSYSTEM=tty"),
+                Err(_) => false,
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod uevent {
+    use std::{io, time::Duration};
+
+    pub struct Socket;
+
+    impl Socket {
+        pub fn open() -> io::Result<Self> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+
+        pub fn wait_for_tty(&mut self, _timeout: Duration) -> bool {
+            false
+        }
+    }
+}
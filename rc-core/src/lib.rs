@@ -1,2 +1,13 @@
+#[cfg(feature = "tokio")]
+pub mod async_serial;
+pub mod autobaud;
+pub mod bridge;
 pub mod cli;
-pub mod serial;
\ No newline at end of file
+pub mod hotplug;
+pub mod port_lock;
+pub mod port_match;
+pub mod pty;
+pub mod rfc2217;
+pub mod serial;
+pub mod text;
+pub mod transport;
\ No newline at end of file
@@ -0,0 +1,218 @@
+use std::{fmt, io, path::{Path, PathBuf}};
+
+/// A process that has a port open or locked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holder {
+    pub pid: u32,
+    pub name: Option<String>,
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name} (pid {})", self.pid),
+            None => write!(f, "pid {}", self.pid),
+        }
+    }
+}
+
+/// A UUCP-style lock file, e.g. `/var/lock/LCK..ttyUSB0`, as honoured by
+/// minicom, picocom and most other terminal programs. Removed on drop.
+pub struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    const DIRS: [&'static str; 3] = ["/var/lock", "/run/lock", "/var/spool/lock"];
+
+    /// Creates the lock file for `device`, taking over stale ones left by
+    /// processes that are gone. Returns `None` where there is no lock
+    /// directory this user may write to, which is common and not an error.
+    #[cfg(unix)]
+    pub fn acquire(device: &str) -> io::Result<Option<Self>> {
+        use std::{fs::OpenOptions, io::Write};
+
+        let Some(path) = Self::path(device) else {
+            return Ok(None);
+        };
+
+        // A second try after removing a stale lock; losing that race means
+        // somebody else just locked the port.
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    // HDB UUCP format: the pid as ten right-aligned digits.
+                    writeln!(file, "{:>10}", std::process::id())?;
+                    return Ok(Some(Self { path }));
+                },
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match Self::owner(&path) {
+                    Some(holder) => return Err(in_use(device, Some(&holder))),
+                    None => {
+                        std::fs::remove_file(&path).ok();
+                    },
+                },
+                Err(e) if matches!(e.kind(), io::ErrorKind::PermissionDenied | io::ErrorKind::NotFound) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(in_use(device, None))
+    }
+
+    #[cfg(not(unix))]
+    pub fn acquire(_device: &str) -> io::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// `LCK..` followed by the device path below `/dev`, with symlinks such as
+    /// `/dev/serial/by-id/...` resolved so every name of a port shares one lock.
+    fn path(device: &str) -> Option<PathBuf> {
+        let dir = Self::DIRS.iter().map(PathBuf::from).find(|dir| dir.is_dir())?;
+        let device = std::fs::canonicalize(device).unwrap_or_else(|_| PathBuf::from(device));
+        let name = device.strip_prefix("/dev").unwrap_or(&device).to_string_lossy().trim_start_matches('/').replace('/', "_");
+
+        Some(dir.join(format!("LCK..{name}")))
+    }
+
+    /// The live process a lock file names, if any.
+    fn owner(path: &Path) -> Option<Holder> {
+        let contents = std::fs::read(path).ok()?;
+
+        // Old-style lock files hold the pid as a binary int.
+        let pid = match std::str::from_utf8(&contents).ok().and_then(|text| text.trim().parse().ok()) {
+            Some(pid) => pid,
+            None => u32::from_ne_bytes(contents.get(..4)?.try_into().ok()?),
+        };
+
+        is_alive(pid).then(|| Holder { pid, name: process_name(pid) })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Who has `device` open or locked: the owner of its lock file, or else any
+/// process with the device among its open files that we may look at.
+pub fn holder(device: &str) -> Option<Holder> {
+    holders(&[device]).pop().map(|(_, holder)| holder)
+}
+
+/// [`holder`] for several devices at once, with a single pass over the open
+/// files of all processes. Devices nobody holds are left out.
+pub fn holders(devices: &[&str]) -> Vec<(String, Holder)> {
+    let mut holders = Vec::new();
+    let mut unlocked = Vec::new();
+
+    for &device in devices {
+        match LockFile::path(device).and_then(|path| LockFile::owner(&path)) {
+            Some(holder) => holders.push((device.to_string(), holder)),
+            None => unlocked.push(device),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut wanted: Vec<(&str, PathBuf)> = unlocked.into_iter()
+            .filter_map(|device| std::fs::canonicalize(device).ok().map(|path| (device, path)))
+            .collect();
+
+        let entries = match wanted.is_empty() {
+            true => None,
+            false => std::fs::read_dir("/proc").ok(),
+        };
+
+        for entry in entries.into_iter().flatten().flatten() {
+            let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse().ok()) else {
+                continue;
+            };
+
+            // Other users' processes can't be looked into without root.
+            let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+                continue;
+            };
+
+            for target in fds.flatten().filter_map(|fd| std::fs::read_link(fd.path()).ok()) {
+                if let Some(index) = wanted.iter().position(|(_, path)| *path == target) {
+                    let (device, _) = wanted.swap_remove(index);
+                    holders.push((device.to_string(), Holder { pid, name: process_name(pid) }));
+                }
+            }
+
+            if wanted.is_empty() {
+                break;
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = unlocked;
+
+    holders
+}
+
+/// Keeps other processes from opening the port while `fd` is open: `flock`
+/// for programs that check it, and `TIOCEXCL` for all others without root.
+#[cfg(unix)]
+pub(crate) fn lock_exclusive(fd: std::os::unix::io::RawFd) -> io::Result<()> {
+    if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if unsafe { libc::ioctl(fd, libc::TIOCEXCL) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Undoes `TIOCEXCL`, which would otherwise outlive our descriptor while
+/// another one keeps the tty open.
+#[cfg(unix)]
+pub(crate) fn unlock_exclusive(fd: std::os::unix::io::RawFd) {
+    unsafe { libc::ioctl(fd, libc::TIOCNXCL) };
+}
+
+/// Names the holder in errors that say the port is busy.
+pub(crate) fn describe_busy(device: &str, error: io::Error) -> io::Error {
+    #[cfg(unix)]
+    if matches!(error.raw_os_error(), Some(libc::EBUSY | libc::EWOULDBLOCK)) {
+        return in_use(device, holder(device).as_ref());
+    }
+
+    #[cfg(not(unix))]
+    let _ = device;
+
+    error
+}
+
+fn in_use(device: &str, holder: Option<&Holder>) -> io::Error {
+    let message = match holder {
+        Some(holder) => format!("{device} is in use by {holder}"),
+        None => format!("{device} is in use by another program"),
+    };
+
+    io::Error::new(io::ErrorKind::ResourceBusy, message)
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+
+    // EPERM means it exists but belongs to somebody else.
+    pid > 0 && (unsafe { libc::kill(pid, 0) } == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: u32) -> bool {
+    false
+}
+
+fn process_name(pid: u32) -> Option<String> {
+    let name = std::fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(name.trim_end().to_string())
+}
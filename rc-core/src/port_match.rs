@@ -0,0 +1,107 @@
+use std::{fmt, io};
+
+use crate::serial::{PortInfo, Serial};
+
+/// Picks a port by what is plugged in rather than by its path, e.g.
+/// `usb:0403:6001`, `serial:FT12345` or `product:"CP2102*"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortMatch {
+    Usb { vid: u16, pid: Option<u16> },
+    Serial(String),
+    /// Case-insensitive glob where `*` matches any run of characters and `?` a single one.
+    Product(String),
+}
+
+impl PortMatch {
+    /// Parses a device string. Returns `None` for anything that is not a
+    /// match rule, such as a plain path.
+    pub fn parse(device: &str) -> Option<Result<Self, String>> {
+        let (kind, value) = device.split_once(':')?;
+
+        let rule = match kind {
+            "usb" => {
+                let (vid, pid) = match value.split_once(':') {
+                    Some((vid, pid)) => (vid, Some(pid)),
+                    None => (value, None),
+                };
+
+                let parse_id = |id: &str| u16::from_str_radix(id, 16).map_err(|_| format!("Expected usb:VID:PID in hex, e.g. usb:0403:6001, got {device}"));
+
+                Self::Usb {
+                    vid: match parse_id(vid) {
+                        Ok(vid) => vid,
+                        Err(e) => return Some(Err(e)),
+                    },
+                    pid: match pid.map(parse_id).transpose() {
+                        Ok(pid) => pid,
+                        Err(e) => return Some(Err(e)),
+                    },
+                }
+            },
+            "serial" => Self::Serial(value.to_string()),
+            "product" => Self::Product(value.trim_matches('"').to_string()),
+            _ => return None,
+        };
+
+        Some(Ok(rule))
+    }
+
+    pub fn matches(&self, port: &PortInfo) -> bool {
+        match self {
+            PortMatch::Usb { vid, pid } => port.vid == Some(*vid) && (pid.is_none() || port.pid == *pid),
+            PortMatch::Serial(serial_number) => port.serial_number.as_ref() == Some(serial_number),
+            PortMatch::Product(pattern) => port.product.as_ref().is_some_and(|product| {
+                glob(pattern.to_lowercase().as_bytes(), product.to_lowercase().as_bytes())
+            }),
+        }
+    }
+
+    /// Name of the one port that matches.
+    pub fn resolve(&self, ports: &[PortInfo]) -> Result<String, String> {
+        let matching: Vec<&str> = ports.iter().filter(|port| self.matches(port)).map(|port| port.name.as_str()).collect();
+
+        match matching.as_slice() {
+            [name] => Ok(name.to_string()),
+            [] => Err(format!("No port matches {self}")),
+            names => Err(format!("{self} matches several ports: {}", names.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for PortMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortMatch::Usb { vid, pid: Some(pid) } => write!(f, "usb:{vid:04x}:{pid:04x}"),
+            PortMatch::Usb { vid, pid: None } => write!(f, "usb:{vid:04x}"),
+            PortMatch::Serial(serial_number) => write!(f, "serial:{serial_number}"),
+            PortMatch::Product(pattern) => write!(f, "product:\"{pattern}\""),
+        }
+    }
+}
+
+/// Turns a match rule into the name of the port it matches right now. Any
+/// other device name is returned unchanged.
+pub fn resolve_device(device: &str) -> io::Result<String> {
+    match PortMatch::parse(device) {
+        None => Ok(device.to_string()),
+        Some(Err(e)) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        Some(Ok(rule)) => {
+            let ports = Serial::available_ports().map_err(|e| io::Error::other(e.to_string()))?;
+
+            rule.resolve(&ports).map_err(|e| match ports.iter().any(|port| rule.matches(port)) {
+                true => io::Error::other(e),
+                false => io::Error::new(io::ErrorKind::NotFound, e),
+            })
+        },
+    }
+}
+
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.split_first(), text.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => glob(rest, text) || (!text.is_empty() && glob(pattern, &text[1..])),
+        (Some((b'?', rest)), Some((_, text))) => glob(rest, text),
+        (Some((expected, rest)), Some((actual, text))) => expected == actual && glob(rest, text),
+        _ => false,
+    }
+}
@@ -0,0 +1,163 @@
+use flume::Receiver;
+use std::{
+    fs::File,
+    io::{self, Write},
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex},
+};
+
+static ACTIVE: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Slave paths of the pseudo-terminals currently open in this process.
+pub fn active_paths() -> Vec<String> {
+    ACTIVE.lock().unwrap().clone()
+}
+
+/// A pseudo-terminal whose slave side other programs can open as if it was
+/// the serial port.
+///
+/// Like `Bridge`, it does not own the port: data received from the port is
+/// passed to `write`, and whatever the other program writes to the slave
+/// comes back from `try_recv`.
+pub struct Pty {
+    master: Arc<File>,
+    _slave: File,
+    path: String,
+    running: Arc<AtomicBool>,
+    data: Receiver<Vec<u8>>,
+    error: Receiver<io::Error>,
+}
+
+impl Pty {
+    #[cfg(unix)]
+    pub fn open() -> io::Result<Self> {
+        use flume::unbounded;
+        use std::{ffi::CStr, fs::OpenOptions, io::Read, os::unix::{fs::OpenOptionsExt, io::{AsRawFd, FromRawFd}}, thread};
+
+        static PTSNAME: Mutex<()> = Mutex::new(());
+
+        let master = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            File::from_raw_fd(fd)
+        };
+
+        let path = unsafe {
+            if libc::grantpt(master.as_raw_fd()) != 0 || libc::unlockpt(master.as_raw_fd()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let _ptsname = PTSNAME.lock().unwrap();
+            let name = libc::ptsname(master.as_raw_fd());
+
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+
+            CStr::from_ptr(name).to_string_lossy().to_string()
+        };
+
+        // Keeping the slave open stops reads on the master failing with EIO
+        // while no other program has it open.
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            libc::cfmakeraw(&mut termios);
+
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let master = Arc::new(master);
+        let running = Arc::new(AtomicBool::new(true));
+        let (data_sender, data_receiver) = unbounded();
+        let (error_sender, error_receiver) = unbounded();
+
+        let receive_master = master.clone();
+        let receive_running = running.clone();
+
+        thread::spawn(move || {
+            let mut buf = [0u8; 1000];
+
+            while receive_running.load(Ordering::Relaxed) {
+                let mut poll_fd = libc::pollfd {
+                    fd: receive_master.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+
+                if unsafe { libc::poll(&mut poll_fd, 1, 50) } <= 0 {
+                    continue;
+                }
+
+                match (&*receive_master).read(&mut buf) {
+                    Ok(0) => (),
+                    Ok(read_bytes) => {
+                        data_sender.send(buf[..read_bytes].to_vec()).ok();
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(e) => {
+                        error_sender.send(e).ok();
+                        break;
+                    },
+                }
+            }
+        });
+
+        ACTIVE.lock().unwrap().push(path.clone());
+
+        Ok(Self {
+            master,
+            _slave: slave,
+            path,
+            running,
+            data: data_receiver,
+            error: error_receiver,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open() -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "pseudo-terminals are only available on Unix"))
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Writes to the slave side. Data is dropped while nobody reads it.
+    pub fn write(&self, data: &[u8]) {
+        (&*self.master).write_all(data).ok();
+    }
+
+    pub fn try_recv(&self) -> Option<Vec<u8>> {
+        self.data.try_recv().ok()
+    }
+
+    /// Why reading from the slave side stopped. Nothing more comes from
+    /// `try_recv` after that.
+    pub fn try_recv_error(&self) -> Option<io::Error> {
+        self.error.try_recv().ok()
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        ACTIVE.lock().unwrap().retain(|path| *path != self.path);
+    }
+}
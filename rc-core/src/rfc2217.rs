@@ -0,0 +1,260 @@
+//! Telnet framing and the RFC 2217 COM-PORT-OPTION command set.
+
+use serial2::{CharSize, FlowControl, StopBits};
+
+use crate::serial::Parity;
+use crate::transport::ModemStatus;
+
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+
+pub const BINARY: u8 = 0;
+pub const SUPPRESS_GO_AHEAD: u8 = 3;
+pub const COM_PORT_OPTION: u8 = 44;
+
+/// Client to server command codes. The server answers with the same code plus
+/// `SERVER_OFFSET`.
+pub mod command {
+    pub const SIGNATURE: u8 = 0;
+    pub const SET_BAUDRATE: u8 = 1;
+    pub const SET_DATASIZE: u8 = 2;
+    pub const SET_PARITY: u8 = 3;
+    pub const SET_STOPSIZE: u8 = 4;
+    pub const SET_CONTROL: u8 = 5;
+    pub const NOTIFY_LINESTATE: u8 = 6;
+    pub const NOTIFY_MODEMSTATE: u8 = 7;
+    pub const FLOWCONTROL_SUSPEND: u8 = 8;
+    pub const FLOWCONTROL_RESUME: u8 = 9;
+    pub const SET_LINESTATE_MASK: u8 = 10;
+    pub const SET_MODEMSTATE_MASK: u8 = 11;
+    pub const PURGE_DATA: u8 = 12;
+
+    pub const SERVER_OFFSET: u8 = 100;
+}
+
+/// Values of the `SET-CONTROL` command.
+pub mod control {
+    pub const FLOW_NONE: u8 = 1;
+    pub const FLOW_XONXOFF: u8 = 2;
+    pub const FLOW_HARDWARE: u8 = 3;
+    pub const BREAK_REQUEST: u8 = 4;
+    pub const BREAK_ON: u8 = 5;
+    pub const BREAK_OFF: u8 = 6;
+    pub const DTR_REQUEST: u8 = 7;
+    pub const DTR_ON: u8 = 8;
+    pub const DTR_OFF: u8 = 9;
+    pub const RTS_REQUEST: u8 = 10;
+    pub const RTS_ON: u8 = 11;
+    pub const RTS_OFF: u8 = 12;
+}
+
+/// Bits of the `NOTIFY-MODEMSTATE` value.
+pub mod modem_state {
+    pub const CD: u8 = 0x80;
+    pub const RI: u8 = 0x40;
+    pub const DSR: u8 = 0x20;
+    pub const CTS: u8 = 0x10;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Will(u8),
+    Wont(u8),
+    Do(u8),
+    Dont(u8),
+    Subnegotiation(u8, Vec<u8>),
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+enum State {
+    #[default]
+    Data,
+    Iac,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Incremental Telnet decoder, separating data bytes from protocol events.
+#[derive(Debug, Default)]
+pub struct Telnet {
+    state: State,
+    subnegotiation: Vec<u8>,
+}
+
+impl Telnet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decode(&mut self, input: &[u8], data: &mut Vec<u8>, events: &mut Vec<Event>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => {
+                    data.push(byte);
+                    State::Data
+                },
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                },
+                (State::Iac, WILL | WONT | DO | DONT) => State::Negotiation(byte),
+                (State::Iac, SB) => {
+                    self.subnegotiation.clear();
+                    State::Subnegotiation
+                },
+                (State::Iac, _) => State::Data,
+                (State::Negotiation(verb), option) => {
+                    events.push(match verb {
+                        WILL => Event::Will(option),
+                        WONT => Event::Wont(option),
+                        DO => Event::Do(option),
+                        _ => Event::Dont(option),
+                    });
+                    State::Data
+                },
+                (State::Subnegotiation, IAC) => State::SubnegotiationIac,
+                (State::Subnegotiation, _) => {
+                    self.subnegotiation.push(byte);
+                    State::Subnegotiation
+                },
+                (State::SubnegotiationIac, IAC) => {
+                    self.subnegotiation.push(IAC);
+                    State::Subnegotiation
+                },
+                (State::SubnegotiationIac, SE) => {
+                    if let Some((&option, payload)) = self.subnegotiation.split_first() {
+                        events.push(Event::Subnegotiation(option, payload.to_vec()));
+                    }
+                    State::Data
+                },
+                (State::SubnegotiationIac, _) => State::Subnegotiation,
+            };
+        }
+    }
+}
+
+/// Doubles every IAC byte so data can be sent inside the Telnet stream.
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+
+    for &byte in data {
+        escaped.push(byte);
+
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+
+    escaped
+}
+
+pub fn negotiation(verb: u8, option: u8) -> [u8; 3] {
+    [IAC, verb, option]
+}
+
+pub fn com_port_command(command: u8, value: &[u8]) -> Vec<u8> {
+    let mut frame = vec![IAC, SB, COM_PORT_OPTION, command];
+    frame.extend(escape(value));
+    frame.extend([IAC, SE]);
+    frame
+}
+
+pub fn data_size(char_size: CharSize) -> u8 {
+    match char_size {
+        CharSize::Bits5 => 5,
+        CharSize::Bits6 => 6,
+        CharSize::Bits7 => 7,
+        CharSize::Bits8 => 8,
+    }
+}
+
+pub fn char_size(data_size: u8) -> Option<CharSize> {
+    match data_size {
+        5 => Some(CharSize::Bits5),
+        6 => Some(CharSize::Bits6),
+        7 => Some(CharSize::Bits7),
+        8 => Some(CharSize::Bits8),
+        _ => None,
+    }
+}
+
+pub fn parity_value(parity: Parity) -> u8 {
+    match parity {
+        Parity::None => 1,
+        Parity::Odd => 2,
+        Parity::Even => 3,
+        Parity::Mark => 4,
+        Parity::Space => 5,
+    }
+}
+
+pub fn parity(value: u8) -> Option<Parity> {
+    match value {
+        1 => Some(Parity::None),
+        2 => Some(Parity::Odd),
+        3 => Some(Parity::Even),
+        4 => Some(Parity::Mark),
+        5 => Some(Parity::Space),
+        _ => None,
+    }
+}
+
+pub fn stop_size(stop_bits: StopBits) -> u8 {
+    match stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    }
+}
+
+pub fn stop_bits(value: u8) -> Option<StopBits> {
+    match value {
+        1 => Some(StopBits::One),
+        2 => Some(StopBits::Two),
+        _ => None,
+    }
+}
+
+pub fn flow_control_value(flow_control: FlowControl) -> u8 {
+    match flow_control {
+        FlowControl::None => control::FLOW_NONE,
+        FlowControl::XonXoff => control::FLOW_XONXOFF,
+        FlowControl::RtsCts => control::FLOW_HARDWARE,
+    }
+}
+
+pub fn flow_control(value: u8) -> Option<FlowControl> {
+    match value {
+        control::FLOW_NONE => Some(FlowControl::None),
+        control::FLOW_XONXOFF => Some(FlowControl::XonXoff),
+        control::FLOW_HARDWARE => Some(FlowControl::RtsCts),
+        _ => None,
+    }
+}
+
+pub fn modem_state_value(status: ModemStatus) -> u8 {
+    [
+        (status.cd, modem_state::CD),
+        (status.ri, modem_state::RI),
+        (status.dsr, modem_state::DSR),
+        (status.cts, modem_state::CTS),
+    ]
+    .into_iter()
+    .filter(|(state, _)| *state)
+    .fold(0, |value, (_, bit)| value | bit)
+}
+
+pub fn modem_status(value: u8) -> ModemStatus {
+    ModemStatus {
+        cts: value & modem_state::CTS != 0,
+        dsr: value & modem_state::DSR != 0,
+        ri: value & modem_state::RI != 0,
+        cd: value & modem_state::CD != 0,
+    }
+}
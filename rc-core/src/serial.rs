@@ -1,15 +1,121 @@
 use anyhow::Result;
-use serial2::{SerialPort, CharSize, FlowControl, Parity, StopBits, IntoSettings};
-use flume::{unbounded, Receiver, Sender};
-use std::{sync::Arc, thread, io::Read};
+use serial2::{CharSize, FlowControl, StopBits, IntoSettings};
+use flume::{unbounded, Receiver, RecvTimeoutError, Sender};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    io,
+    path::Path,
+    sync::{atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}, Arc, Condvar, Mutex, RwLock},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
-#[derive(Debug, Clone)]
+use crate::port_match::PortMatch;
+use crate::transport::{self, Capabilities, ConnectionType, LineErrors, ModemStatus, Transport};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SerialConfig {
     pub baudrate: u32,
     pub char_size: CharSize,
     pub parity: Parity,
     pub flow_control: FlowControl,
     pub stop_bits: StopBits,
+    /// Output line states applied right after the port opens; `None` keeps the driver default.
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+    pub pacing: Pacing,
+    /// Have the driver check parity and replace bytes received with parity
+    /// or framing errors by `transport::ERROR_SUBSTITUTE`. Unix serial ports only.
+    pub mark_errors: bool,
+    pub rs485: Rs485,
+    /// Keep other programs off the port while it is open, with a UUCP lock
+    /// file, `flock` and `TIOCEXCL`. Windows always opens ports exclusively.
+    pub exclusive: bool,
+}
+
+/// Parity bit, where mark and space keep it always set or always clear.
+/// Multidrop buses use it as a 9th data bit that flags address bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+impl Parity {
+    /// Mark or space, which Linux calls stick parity.
+    pub fn is_stick(&self) -> bool {
+        matches!(self, Parity::Mark | Parity::Space)
+    }
+}
+
+impl From<serial2::Parity> for Parity {
+    fn from(parity: serial2::Parity) -> Self {
+        match parity {
+            serial2::Parity::None => Parity::None,
+            serial2::Parity::Odd => Parity::Odd,
+            serial2::Parity::Even => Parity::Even,
+        }
+    }
+}
+
+/// What to wait for after sending a line, before sending the next one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LineWait {
+    #[default]
+    Nothing,
+    /// The line coming back from the device.
+    Echo,
+    Prompt(String),
+}
+
+/// Slows sending down for devices that drop characters at full speed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pacing {
+    pub char_delay: Duration,
+    pub line_delay: Duration,
+    pub line_wait: LineWait,
+}
+
+impl Pacing {
+    /// Longest wait for an echo or prompt before sending the next line anyway.
+    pub const WAIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+    pub fn is_enabled(&self) -> bool {
+        !self.char_delay.is_zero() || !self.line_delay.is_zero() || self.line_wait != LineWait::Nothing
+    }
+}
+
+/// Half-duplex RS-485, where RTS switches the transceiver between sending
+/// and receiving. The Linux driver does the switching where it supports
+/// RS-485 mode; otherwise `Serial` toggles RTS around every write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rs485 {
+    pub enabled: bool,
+    /// RTS level while sending; RTS takes the other level in between.
+    pub rts_on_send: bool,
+    pub delay_before_send: Duration,
+    pub delay_after_send: Duration,
+    /// Keep the receiver on while sending, so our own data comes back.
+    pub rx_during_tx: bool,
+    /// Drop our own data from what is received, for transceivers that echo it.
+    pub remove_echo: bool,
+}
+
+impl Default for Rs485 {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rts_on_send: true,
+            delay_before_send: Duration::ZERO,
+            delay_after_send: Duration::ZERO,
+            rx_during_tx: false,
+            remove_echo: false,
+        }
+    }
 }
 
 impl Default for SerialConfig {
@@ -20,125 +126,1126 @@ impl Default for SerialConfig {
             flow_control: FlowControl::None,
             parity: Parity::None,
             stop_bits: StopBits::One,
+            dtr: None,
+            rts: None,
+            pacing: Pacing::default(),
+            mark_errors: false,
+            rs485: Rs485::default(),
+            exclusive: true,
         }
     }
 }
 
+impl SerialConfig {
+    /// Char size, parity and stop bits in the usual short form, e.g. `8N1`.
+    pub fn framing(&self) -> String {
+        let char_size = match self.char_size {
+            CharSize::Bits5 => 5,
+            CharSize::Bits6 => 6,
+            CharSize::Bits7 => 7,
+            CharSize::Bits8 => 8,
+        };
+
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+            Parity::Mark => 'M',
+            Parity::Space => 'S',
+        };
+
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+
+        format!("{char_size}{parity}{stop_bits}")
+    }
+
+    /// Describes every line setting where `applied` differs from this requested configuration.
+    pub fn mismatches(&self, applied: &SerialConfig) -> Vec<String> {
+        let mut mismatches = Vec::new();
+
+        if self.baudrate != applied.baudrate {
+            mismatches.push(format!("baud rate {} was applied as {}", self.baudrate, applied.baudrate));
+        }
+
+        if self.char_size != applied.char_size {
+            mismatches.push(format!("char size {:?} was applied as {:?}", self.char_size, applied.char_size));
+        }
+
+        if self.parity != applied.parity {
+            mismatches.push(format!("parity {:?} was applied as {:?}", self.parity, applied.parity));
+        }
+
+        if self.stop_bits != applied.stop_bits {
+            mismatches.push(format!("stop bits {:?} were applied as {:?}", self.stop_bits, applied.stop_bits));
+        }
+
+        if self.flow_control != applied.flow_control {
+            mismatches.push(format!("flow control {:?} was applied as {:?}", self.flow_control, applied.flow_control));
+        }
+
+        mismatches
+    }
+}
+
 impl IntoSettings for SerialConfig {
     fn apply_to_settings(self, settings: &mut serial2::Settings) -> std::io::Result<()> {
         settings.set_baud_rate(self.baudrate)?;
         settings.set_char_size(self.char_size);
         settings.set_stop_bits(self.stop_bits);
-        settings.set_parity(self.parity);
+        // Stick parity is the odd or even bit plus CMSPAR, which the
+        // transport sets on top.
+        settings.set_parity(match self.parity {
+            Parity::None => serial2::Parity::None,
+            Parity::Odd | Parity::Mark => serial2::Parity::Odd,
+            Parity::Even | Parity::Space => serial2::Parity::Even,
+        });
         settings.set_flow_control(self.flow_control);
         Ok(())
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortType {
+    Usb,
+    Pci,
+    Bluetooth,
+    Unknown,
+}
+
+impl std::fmt::Display for PortType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortType::Usb => write!(f, "USB"),
+            PortType::Pci => write!(f, "PCI"),
+            PortType::Bluetooth => write!(f, "Bluetooth"),
+            PortType::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+/// A port reported by the system, with whatever it tells about the hardware behind it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortInfo {
+    pub name: String,
+    pub port_type: PortType,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+impl PortInfo {
+    /// Product (or manufacturer) and USB ID, e.g. `CP2102 USB to UART (10c4:ea60)`.
+    pub fn description(&self) -> String {
+        let name = self.product.as_ref().or(self.manufacturer.as_ref());
+
+        match (name, self.vid, self.pid) {
+            (Some(name), Some(vid), Some(pid)) => format!("{name} ({vid:04x}:{pid:04x})"),
+            (None, Some(vid), Some(pid)) => format!("{vid:04x}:{pid:04x}"),
+            (Some(name), _, _) => name.clone(),
+            _ => String::new(),
+        }
+    }
+}
+
+impl From<serialport::SerialPortInfo> for PortInfo {
+    fn from(port: serialport::SerialPortInfo) -> Self {
+        let mut info = Self {
+            name: port.port_name,
+            port_type: PortType::Unknown,
+            vid: None,
+            pid: None,
+            serial_number: None,
+            manufacturer: None,
+            product: None,
+        };
+
+        match port.port_type {
+            serialport::SerialPortType::UsbPort(usb) => {
+                info.port_type = PortType::Usb;
+                info.vid = Some(usb.vid);
+                info.pid = Some(usb.pid);
+                info.serial_number = usb.serial_number;
+                info.manufacturer = usb.manufacturer;
+                info.product = usb.product;
+            },
+            serialport::SerialPortType::PciPort => info.port_type = PortType::Pci,
+            serialport::SerialPortType::BluetoothPort => info.port_type = PortType::Bluetooth,
+            serialport::SerialPortType::Unknown => (),
+        }
+
+        info
+    }
+}
+
+/// What happened to the port, reported from the worker threads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialEvent {
+    Connected(String),
+    /// The port was closed by `stop`, or was lost with the given error. A
+    /// lost port stays unusable until a new `start` or `reconnect`.
+    Disconnected(Option<String>),
+    /// Reading failed, but the port may still recover.
+    ReadError(String),
+    /// Queued data could not be written and was dropped.
+    WriteError(String),
+    /// Setting or sending BREAK failed.
+    BreakError(String),
+    /// This many received bytes were lost because the UART or the driver
+    /// could not keep up. Only reported where the driver counts overruns.
+    Overrun(u32),
+}
+
+/// Progress of data handed to `Serial::send`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransmitStatus {
+    /// Bytes waiting to be written.
+    pub queued: usize,
+    /// Bytes the port accepted since this `Serial` was created.
+    pub written: u64,
+    /// Flow control is on and the port has accepted no data for a while,
+    /// so the device is holding off with RTS/CTS or XOFF.
+    pub blocked: bool,
+}
+
+#[derive(Default)]
+struct TransmitCounters {
+    queued: AtomicUsize,
+    written: AtomicU64,
+    blocked: AtomicBool,
+    /// Without flow control a stall is just a slow or busy port.
+    flow_control: AtomicBool,
+}
+
+impl TransmitCounters {
+    /// How long writes have to stall before the port counts as blocked.
+    const BLOCKED_AFTER: Duration = Duration::from_millis(200);
+
+    fn set_flow_control(&self, flow_control: FlowControl) {
+        self.flow_control.store(flow_control != FlowControl::None, Ordering::Relaxed);
+    }
+
+    /// A write accepted nothing, the first time at `since`.
+    fn stalled(&self, since: Instant) {
+        if self.flow_control.load(Ordering::Relaxed) && since.elapsed() >= Self::BLOCKED_AFTER {
+            self.blocked.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn wrote(&self, bytes: usize) {
+        self.dequeue(bytes);
+        self.written.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn dropped(&self, bytes: usize) {
+        self.dequeue(bytes);
+    }
+
+    /// Saturates because `stop` may clear the count while a write is in progress.
+    fn dequeue(&self, bytes: usize) {
+        self.queued.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| Some(queued.saturating_sub(bytes))).ok();
+        self.blocked.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Recently received data, so paced sending can wait for an echo or prompt.
+#[derive(Default)]
+struct ReceiveWatch {
+    data: Mutex<Vec<u8>>,
+    received: Condvar,
+}
+
+impl ReceiveWatch {
+    const CAPACITY: usize = 4096;
+
+    fn push(&self, data: &[u8]) {
+        let mut buffer = self.data.lock().unwrap();
+        buffer.extend_from_slice(data);
+
+        let excess = buffer.len().saturating_sub(Self::CAPACITY);
+        buffer.drain(..excess);
+
+        self.received.notify_all();
+    }
+
+    fn clear(&self) {
+        self.data.lock().unwrap().clear();
+    }
+
+    /// Waits until `expected` was received since the last `clear`, or the
+    /// timeout passes. Returns `false` if `stop` came first.
+    fn wait_for(&self, expected: &[u8], timeout: Duration, stop: &Receiver<()>) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = self.data.lock().unwrap();
+
+        while !buffer.windows(expected.len()).any(|window| window == expected) {
+            let now = Instant::now();
+
+            if now >= deadline {
+                break;
+            }
+
+            if stop.is_disconnected() {
+                return false;
+            }
+
+            buffer = self.received.wait_timeout(buffer, (deadline - now).min(Duration::from_millis(50))).unwrap().0;
+        }
+
+        true
+    }
+}
+
+/// Bytes sent that the transceiver is expected to echo, so they can be
+/// dropped from what is received.
+#[derive(Default)]
+struct EchoFilter {
+    expected: Mutex<VecDeque<u8>>,
+    deadline: Mutex<Option<Instant>>,
+}
+
+impl EchoFilter {
+    /// How long the echo may lag behind; after that the bytes are assumed lost.
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn sent(&self, data: &[u8]) {
+        self.expected.lock().unwrap().extend(data);
+        *self.deadline.lock().unwrap() = Some(Instant::now() + Self::TIMEOUT);
+    }
+
+    /// Strips the echo from the start of `data`. Anything else on the line
+    /// means the echo won't come, so the rest is forgotten.
+    fn remove<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        let mut expected = self.expected.lock().unwrap();
+        let mut deadline = self.deadline.lock().unwrap();
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            expected.clear();
+        }
+
+        let echoed = data.iter().zip(expected.iter()).take_while(|(received, sent)| received == sent).count();
+        expected.drain(..echoed);
+
+        if echoed < data.len() {
+            expected.clear();
+        }
+
+        *deadline = (!expected.is_empty()).then(|| Instant::now() + Self::TIMEOUT);
+        &data[echoed..]
+    }
+
+    fn clear(&self) {
+        self.expected.lock().unwrap().clear();
+    }
+}
+
+/// RS-485 handling left to `Serial`.
+#[derive(Debug, Clone, Default)]
+struct HalfDuplex {
+    config: Rs485,
+    /// The driver has no RS-485 mode, so the transmit thread toggles RTS.
+    software_rts: bool,
+}
+
+/// Work for the transmit thread, handled strictly in order.
+enum Output {
+    Data(Vec<u8>),
+    /// `address` with mark parity and `data` with space parity, then back
+    /// to the parity of `config`.
+    Addressed {
+        address: u8,
+        data: Vec<u8>,
+        config: SerialConfig,
+    },
+    SetBreak(bool),
+    Break(Duration),
+}
+
+/// What is needed to open the same port again after it went away.
+struct Session {
+    device: String,
+    config: SerialConfig,
+    usb_serial: Option<String>,
+}
+
+/// Where the worker threads of one session report to. Replaced by every
+/// `start`, so nothing left unread can leak into the next session.
+struct Channels {
+    data: (Sender<Vec<u8>>, Receiver<Vec<u8>>),
+    modem: (Sender<ModemStatus>, Receiver<ModemStatus>),
+    line_errors: (Sender<LineErrors>, Receiver<LineErrors>),
+    events: (Sender<SerialEvent>, Receiver<SerialEvent>),
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Self {
+            data: unbounded(),
+            modem: unbounded(),
+            line_errors: unbounded(),
+            events: unbounded(),
+        }
+    }
+}
+
+/// Threads of the running session, told to finish and joined by `stop`.
+struct Workers {
+    /// Dropped to stop the threads; they see the channel disconnect.
+    stop: Sender<()>,
+    output: Sender<Output>,
+    threads: Vec<JoinHandle<()>>,
+}
+
 pub struct Serial {
-    transmit_state_channel: (Sender<()>, Receiver<()>),
-    receive_state_channel: (Sender<()>, Receiver<()>),
-    data_channel: (Sender<String>, Receiver<String>),
-    output_channel: (Sender<String>, Receiver<String>),
+    channels: RwLock<Channels>,
+    transmit_counters: Arc<TransmitCounters>,
+    pacing: Arc<RwLock<Pacing>>,
+    receive_watch: Arc<ReceiveWatch>,
+    half_duplex: Arc<RwLock<HalfDuplex>>,
+    echo_filter: Arc<EchoFilter>,
+    transport: RwLock<Option<Arc<dyn Transport>>>,
+    session: RwLock<Option<Session>>,
+    workers: Mutex<Option<Workers>>,
 }
 
 impl Default for Serial {
     fn default() -> Self {
         Self {
-            transmit_state_channel: unbounded(),
-            receive_state_channel: unbounded(),
-            data_channel: unbounded(),
-            output_channel: unbounded(),
+            channels: RwLock::default(),
+            transmit_counters: Arc::default(),
+            pacing: Arc::default(),
+            receive_watch: Arc::default(),
+            half_duplex: Arc::default(),
+            echo_filter: Arc::default(),
+            transport: RwLock::new(None),
+            session: RwLock::new(None),
+            workers: Mutex::new(None),
         }
     }
 }
 
+impl Drop for Serial {
+    fn drop(&mut self) {
+        self.stop().ok();
+    }
+}
+
 impl Serial {
+    const MODEM_POLL_INTERVAL: Duration = Duration::from_millis(50);
+    /// Read errors in a row after which the port counts as lost even if the
+    /// error itself does not say so.
+    const MAX_READ_ERRORS: u32 = 10;
+    /// How often to look for a port that went away.
+    pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn available_ports() -> Result<Vec<String>> {
+    pub fn available_ports() -> Result<Vec<PortInfo>> {
         let ports = serialport::available_ports()?;
-        let names: Vec<String> = ports.iter().map(|port| port.port_name.clone()).collect();
-        Ok(names)
+        Ok(ports.into_iter().map(PortInfo::from).collect())
+    }
+
+    /// Pseudo-terminals opened by this process that other programs can attach to.
+    pub fn virtual_ports() -> Vec<String> {
+        crate::pty::active_paths()
     }
 
+    /// USB serial number of the adapter behind `port_name`, if the platform reports one.
+    pub fn usb_serial_number(port_name: &str) -> Option<String> {
+        Self::available_ports()
+            .ok()?
+            .into_iter()
+            .find(|port| port.name == port_name)
+            .and_then(|port| port.serial_number)
+    }
+
+    /// Looks for a port that went away. With a USB serial number that is
+    /// wherever the adapter shows up now, preferably its old path, since
+    /// another adapter may have taken that path over. Otherwise it is the
+    /// old path once that is back.
+    pub fn find_port(port_name: &str, usb_serial: Option<&str>) -> Option<String> {
+        let ports = Self::available_ports().unwrap_or_default();
+
+        let Some(usb_serial) = usb_serial else {
+            let present = Path::new(port_name).exists() || ports.iter().any(|port| port.name == port_name);
+            return present.then(|| port_name.to_string());
+        };
+
+        // Also the old path when it is a symlink such as /dev/serial/by-id/...
+        let old_path = std::fs::canonicalize(port_name).ok();
+        let is_old_path = |name: &str| {
+            name == port_name || old_path.as_ref().is_some_and(|old_path| std::fs::canonicalize(name).is_ok_and(|path| path == *old_path))
+        };
+
+        let matching: Vec<String> = ports.into_iter()
+            .filter(|port| port.serial_number.as_deref() == Some(usb_serial))
+            .map(|port| port.name)
+            .collect();
+
+        if matching.iter().any(|name| is_old_path(name)) {
+            return Some(port_name.to_string());
+        }
+
+        matching.into_iter().next()
+    }
+
+    /// Opens `port_name`, closing whatever port was open before.
     pub fn start(&self, port_name: &str, config: SerialConfig) -> Result<()> {
-        let (_, transmit_state_channel) = self.transmit_state_channel.clone();
-        let (_, receive_state_channel) = self.receive_state_channel.clone();
+        // The old port has to be closed before the same device can be opened again.
+        self.stop()?;
+
+        let transport = transport::open(port_name, &config)?;
+
+        if let Some(dtr) = config.dtr {
+            transport.set_dtr(dtr)?;
+        }
+
+        if let Some(rts) = config.rts {
+            transport.set_rts(rts)?;
+        }
+
+        self.apply_rs485(transport.as_ref(), &config.rs485)?;
+        self.set_pacing(config.pacing.clone());
+
+        let usb_serial = match ConnectionType::parse(port_name) {
+            (ConnectionType::Serial, _) => Self::usb_serial_number(port_name),
+            _ => None,
+        };
+
+        *self.session.write().unwrap() = Some(Session {
+            device: port_name.to_string(),
+            config,
+            usb_serial,
+        });
+
+        self.start_with(transport.into())?;
+        self.channels.read().unwrap().events.0.send(SerialEvent::Connected(port_name.to_string()))?;
+        Ok(())
+    }
+
+    /// Opens the port of the last `start` again with the current settings,
+    /// following a USB adapter to its new path if it re-enumerated. Match
+    /// rules are simply resolved again. Returns the device that was opened.
+    pub fn reconnect(&self) -> Result<String> {
+        let (device, config, usb_serial) = match self.session.read().unwrap().as_ref() {
+            Some(session) => (session.device.clone(), session.config.clone(), session.usb_serial.clone()),
+            None => return Err(anyhow::anyhow!("port was never opened")),
+        };
+
+        let device = match ConnectionType::parse(&device) {
+            (ConnectionType::Serial, _) if PortMatch::parse(&device).is_none() => Self::find_port(&device, usb_serial.as_deref())
+                .ok_or_else(|| anyhow::anyhow!("{device} is not present"))?,
+            _ => device,
+        };
+
+        self.start(&device, config)?;
+        Ok(device)
+    }
 
-        let (data_sender, _) = self.data_channel.clone();
-        let (_, output_receiver) = self.output_channel.clone();
+    /// Runs the receive and transmit threads on `transport`, stopping the
+    /// previous session first.
+    pub fn start_with(&self, transport: Arc<dyn Transport>) -> Result<()> {
+        self.stop()?;
 
-        let mut port = SerialPort::open(port_name, config)?;
-        port.set_read_timeout(std::time::Duration::from_millis(10))?;
-        port.discard_buffers()?;
+        let (stop_sender, receive_stop) = unbounded();
+        let transmit_stop = receive_stop.clone();
+        let (output_sender, output_receiver) = unbounded();
 
-        Self::clear_buffer(&mut port);
+        let channels = Channels::default();
+        let data_sender = channels.data.0.clone();
+        let modem_sender = channels.modem.0.clone();
+        let line_error_sender = channels.line_errors.0.clone();
+        let event_sender = channels.events.0.clone();
+        *self.channels.write().unwrap() = channels;
+        let transmit_event_sender = event_sender.clone();
+        let transmit_counters = self.transmit_counters.clone();
+        let pacing = self.pacing.clone();
+        let receive_watch = self.receive_watch.clone();
+        let transmit_receive_watch = self.receive_watch.clone();
+        let half_duplex = self.half_duplex.clone();
+        let echo_filter = self.echo_filter.clone();
+        let transmit_echo_filter = self.echo_filter.clone();
 
-        let shared_port = Arc::new(port);
+        *self.transport.write().unwrap() = Some(transport.clone());
 
-        let receive_port = shared_port.clone();
+        let receive_port = transport.clone();
+        // Counted from here, since the driver keeps its counts across opens.
+        let line_error_baseline = transport.line_errors().ok();
 
-        thread::spawn(move || {
+        let flow_control = transport.settings().map(|settings| settings.flow_control).unwrap_or(FlowControl::None);
+        self.transmit_counters.set_flow_control(flow_control);
+
+        let receive_thread = thread::spawn(move || {
             let mut buf = [0u8; 1000];
+            let monitor_modem = receive_port.capabilities().modem_lines;
+            let mut modem_status = None;
+            let mut modem_polled = Instant::now();
+            let mut line_errors = None;
+            let mut read_errors = 0;
+
+            while !receive_stop.is_disconnected() {
+                if modem_polled.elapsed() >= Self::MODEM_POLL_INTERVAL {
+                    modem_polled = Instant::now();
+
+                    if monitor_modem {
+                        if let Ok(status) = receive_port.modem_status() {
+                            if modem_status != Some(status) {
+                                modem_status = Some(status);
+                                modem_sender.send(status).ok();
+                            }
+                        }
+                    }
+
+                    if let Some(baseline) = &line_error_baseline {
+                        if let Ok(counts) = receive_port.line_errors() {
+                            let counts = counts.since(baseline);
+                            let lost = |errors: &LineErrors| errors.overrun.wrapping_add(errors.buffer_overrun);
+                            let newly_lost = lost(&counts).wrapping_sub(line_errors.as_ref().map_or(0, lost));
 
-            loop {
-                if receive_state_channel.try_recv().is_ok() {
-                    break;
+                            if newly_lost > 0 {
+                                event_sender.send(SerialEvent::Overrun(newly_lost)).ok();
+                            }
+
+                            if line_errors != Some(counts) {
+                                line_errors = Some(counts);
+                                line_error_sender.send(counts).ok();
+                            }
+                        }
+                    }
                 }
 
                 match receive_port.read(buf.as_mut_slice()) {
                     Ok(read_bytes) => {
-                        let s = String::from_utf8_lossy(&buf[..read_bytes]);
-                        data_sender.send(s.to_string()).ok();
+                        read_errors = 0;
+                        let data = echo_filter.remove(&buf[..read_bytes]);
+
+                        if !data.is_empty() {
+                            receive_watch.push(data);
+                            data_sender.send(data.to_vec()).ok();
+                        }
+                    },
+                    Err(ref e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => (),
+                    // `stop` closed the port under the read.
+                    Err(_) if receive_stop.is_disconnected() => break,
+                    Err(e) if Self::is_lost(&e) || read_errors + 1 >= Self::MAX_READ_ERRORS => {
+                        event_sender.send(SerialEvent::Disconnected(Some(e.to_string()))).ok();
+                        break;
+                    },
+                    Err(e) => {
+                        read_errors += 1;
+                        event_sender.send(SerialEvent::ReadError(e.to_string())).ok();
+                        thread::sleep(Self::MODEM_POLL_INTERVAL);
                     },
-                    Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
-                    Err(e) => eprintln!("{e:?}"),
                 }
             }
         });
 
-        let transmit_port = shared_port;
+        let transmit_port = transport;
 
-        thread::spawn(move || {
-            loop {
-                if transmit_state_channel.try_recv().is_ok() {
-                    break;
-                }
+        let transmit_thread = thread::spawn(move || {
+            while !transmit_stop.is_disconnected() {
+                match output_receiver.recv() {
+                    Ok(Output::Data(data)) => {
+                        let pacing = pacing.read().unwrap().clone();
+                        let half_duplex = half_duplex.read().unwrap().clone();
 
-                if let Ok(s) = output_receiver.recv_timeout(std::time::Duration::from_millis(100)) {
-                    if let Ok(_size) = transmit_port.write(s.as_bytes()) {
+                        if half_duplex.config.enabled && half_duplex.config.remove_echo {
+                            transmit_echo_filter.sent(&data);
+                        }
 
-                    }
+                        let result = if pacing.is_enabled() {
+                            Self::write_paced(transmit_port.as_ref(), &data, &pacing, &half_duplex, &transmit_receive_watch, &transmit_counters, &transmit_stop)
+                        } else {
+                            Self::with_transceiver(transmit_port.as_ref(), &half_duplex, || {
+                                Self::write_data(transmit_port.as_ref(), &data, &transmit_counters, &transmit_stop)
+                            })
+                        };
+
+                        match result {
+                            Ok(true) => (),
+                            Ok(false) => break,
+                            Err(_) if transmit_stop.is_disconnected() => break,
+                            Err(e) => {
+                                transmit_event_sender.send(SerialEvent::WriteError(e.to_string())).ok();
+                            },
+                        }
+                    },
+                    Ok(Output::Addressed { address, data, config }) => {
+                        let half_duplex = half_duplex.read().unwrap().clone();
+
+                        if half_duplex.config.enabled && half_duplex.config.remove_echo {
+                            transmit_echo_filter.sent(&[address]);
+                            transmit_echo_filter.sent(&data);
+                        }
+
+                        let result = Self::with_transceiver(transmit_port.as_ref(), &half_duplex, || {
+                            Self::write_addressed(transmit_port.as_ref(), address, &data, &config, &transmit_counters, &transmit_stop)
+                        });
+
+                        match result {
+                            Ok(true) => (),
+                            Ok(false) => break,
+                            Err(_) if transmit_stop.is_disconnected() => break,
+                            Err(e) => {
+                                transmit_event_sender.send(SerialEvent::WriteError(e.to_string())).ok();
+                            },
+                        }
+                    },
+                    Ok(Output::SetBreak(state)) => {
+                        if let Err(e) = Self::set_break_after_flush(transmit_port.as_ref(), state) {
+                            transmit_event_sender.send(SerialEvent::BreakError(e.to_string())).ok();
+                        }
+                    },
+                    Ok(Output::Break(duration)) => {
+                        let result = Self::set_break_after_flush(transmit_port.as_ref(), true).and_then(|_| {
+                            thread::sleep(duration);
+                            transmit_port.set_break(false)
+                        });
+
+                        if let Err(e) = result {
+                            transmit_event_sender.send(SerialEvent::BreakError(e.to_string())).ok();
+                        }
+                    },
+                    // `stop` dropped the sender.
+                    Err(_) => break,
                 }
             }
         });
 
+        *self.workers.lock().unwrap() = Some(Workers {
+            stop: stop_sender,
+            output: output_sender,
+            threads: vec![receive_thread, transmit_thread],
+        });
+
         Ok(())
     }
 
+    /// Closes the port and waits for the worker threads to finish, so the
+    /// device is free again when this returns. Data still queued for sending
+    /// is discarded.
     pub fn stop(&self) -> Result<()> {
-        self.transmit_state_channel.0.send(())?;
-        self.receive_state_channel.0.send(())?;
+        let workers = self.workers.lock().unwrap().take();
+        let transport = self.transport.write().unwrap().take();
+
+        let threads = workers.map(|Workers { stop, output, threads }| {
+            drop((stop, output));
+            threads
+        });
+
+        // Closed before joining, since a thread stuck writing to a peer that
+        // reads nothing only returns once the connection is shut down.
+        let closed = transport.as_ref().map_or(Ok(()), |transport| transport.close());
+
+        for thread in threads.into_iter().flatten() {
+            thread.join().map_err(|_| anyhow::anyhow!("serial worker thread panicked"))?;
+        }
+
+        self.transmit_counters.queued.store(0, Ordering::Relaxed);
+        self.transmit_counters.blocked.store(false, Ordering::Relaxed);
+        self.echo_filter.clear();
+
+        if transport.is_some() {
+            self.channels.read().unwrap().events.0.send(SerialEvent::Disconnected(None))?;
+        }
+
+        closed?;
+        Ok(())
+    }
+
+    pub fn configure(&self, config: &SerialConfig) -> Result<()> {
+        self.set_pacing(config.pacing.clone());
+        self.transmit_counters.set_flow_control(config.flow_control);
+
+        if let Some(transport) = self.transport.read().unwrap().as_ref() {
+            transport.configure(config)?;
+
+            if config.rs485 != self.half_duplex.read().unwrap().config {
+                self.apply_rs485(transport.as_ref(), &config.rs485)?;
+            }
+        }
+
+        if let Some(session) = self.session.write().unwrap().as_mut() {
+            session.config = config.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Changes pacing without touching the line settings; applies from the next `send`.
+    pub fn set_pacing(&self, pacing: Pacing) {
+        if let Some(session) = self.session.write().unwrap().as_mut() {
+            session.config.pacing = pacing.clone();
+        }
+
+        *self.pacing.write().unwrap() = pacing;
+    }
+
+    /// Whether RS-485 is switched by toggling RTS from here, because the
+    /// driver has no RS-485 mode.
+    pub fn rs485_software_rts(&self) -> bool {
+        self.half_duplex.read().unwrap().software_rts
+    }
+
+    /// Line settings the driver actually applied, which may differ from the requested ones.
+    pub fn effective_config(&self) -> Result<SerialConfig> {
+        self.with_transport(|transport| transport.settings())
+    }
+
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.transport.read().unwrap().as_ref().map(|transport| transport.capabilities())
+    }
+
+    pub fn set_dtr(&self, state: bool) -> Result<()> {
+        self.with_transport(|transport| transport.set_dtr(state))
+    }
+
+    pub fn set_rts(&self, state: bool) -> Result<()> {
+        self.with_transport(|transport| transport.set_rts(state))
+    }
+
+    /// Asserts or releases BREAK once everything queued before it has been sent.
+    pub fn set_break(&self, state: bool) -> Result<()> {
+        self.queue_break(Output::SetBreak(state))
+    }
+
+    /// Holds BREAK for `duration` once everything queued before it has been sent.
+    pub fn send_break(&self, duration: Duration) -> Result<()> {
+        self.queue_break(Output::Break(duration))
+    }
+
+    pub fn modem_status(&self) -> Result<ModemStatus> {
+        self.with_transport(|transport| transport.modem_status())
+    }
+
+    /// Queues data for the transmit thread. Write errors are reported as
+    /// `SerialEvent::WriteError`.
+    pub fn send(&self, data: &[u8]) -> Result<()> {
+        let workers = self.workers.lock().unwrap();
+        let workers = workers.as_ref().ok_or_else(|| anyhow::anyhow!("port is not open"))?;
+
+        self.transmit_counters.queued.fetch_add(data.len(), Ordering::Relaxed);
+        workers.output.send(Output::Data(data.to_vec()))?;
+        Ok(())
+    }
+
+    /// Sends `address` with the 9th bit set, as multidrop buses use to pick
+    /// a device, followed by `data` with it clear. The 9th bit is the parity
+    /// bit, so this wants 8 data bits and a port that can do mark and space
+    /// parity; the configured parity is restored afterwards.
+    pub fn send_addressed(&self, address: u8, data: &[u8]) -> Result<()> {
+        if !self.capabilities().is_some_and(|capabilities| capabilities.configure) {
+            return Err(anyhow::anyhow!("9-bit addressing needs a port whose parity can be changed"));
+        }
+
+        let config = self.session.read().unwrap().as_ref().map(|session| session.config.clone()).unwrap_or_default();
+
+        let workers = self.workers.lock().unwrap();
+        let workers = workers.as_ref().ok_or_else(|| anyhow::anyhow!("port is not open"))?;
+
+        self.transmit_counters.queued.fetch_add(1 + data.len(), Ordering::Relaxed);
+        workers.output.send(Output::Addressed { address, data: data.to_vec(), config })?;
+        Ok(())
+    }
+
+    pub fn transmit_status(&self) -> TransmitStatus {
+        TransmitStatus {
+            queued: self.transmit_counters.queued.load(Ordering::Relaxed),
+            written: self.transmit_counters.written.load(Ordering::Relaxed),
+            blocked: self.transmit_counters.blocked.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<Vec<u8>> {
+        self.channels.read().unwrap().data.1.try_recv().ok()
+    }
+
+    /// Input line states, delivered whenever they change while the port is open.
+    pub fn try_recv_modem_status(&self) -> Option<ModemStatus> {
+        self.channels.read().unwrap().modem.1.try_recv().ok()
+    }
+
+    /// Receive errors since the port was opened, delivered once the driver
+    /// first reports them and then whenever they change.
+    pub fn try_recv_line_errors(&self) -> Option<LineErrors> {
+        self.channels.read().unwrap().line_errors.1.try_recv().ok()
+    }
+
+    /// Events of the current session. Whatever an earlier one left unread is
+    /// dropped when `start` opens the next, like its data.
+    pub fn try_recv_event(&self) -> Option<SerialEvent> {
+        self.channels.read().unwrap().events.1.try_recv().ok()
+    }
+
+    fn queue_break(&self, output: Output) -> Result<()> {
+        if !self.capabilities().is_some_and(|capabilities| capabilities.send_break) {
+            return Err(anyhow::anyhow!("BREAK is not supported by this port"));
+        }
+
+        if let Some(workers) = self.workers.lock().unwrap().as_ref() {
+            workers.output.send(output)?;
+        }
 
         Ok(())
     }
 
-    pub fn send(&self, data: &str) {
-        self.output_channel.0.send(data.to_string()).unwrap();
+    /// Tells whether a read error means the device is gone rather than a hiccup.
+    fn is_lost(error: &io::Error) -> bool {
+        if matches!(
+            error.kind(),
+            io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::NotConnected | io::ErrorKind::UnexpectedEof | io::ErrorKind::NotFound
+        ) {
+            return true;
+        }
+
+        #[cfg(unix)]
+        if matches!(error.raw_os_error(), Some(libc::EIO | libc::ENXIO | libc::ENODEV)) {
+            return true;
+        }
+
+        false
     }
 
-    pub fn try_recv(&self) -> Option<String> {
-        self.data_channel.1.try_recv().ok()
+    /// Writes all of `data`, continuing after partial writes and waiting while
+    /// the port accepts nothing. Returns `Ok(false)` if `stop` came first.
+    fn write_data(transport: &dyn Transport, data: &[u8], counters: &TransmitCounters, stop: &Receiver<()>) -> io::Result<bool> {
+        let mut written = 0;
+        let mut stalled = None;
+
+        while written < data.len() {
+            match transport.write(&data[written..]) {
+                Ok(0) => {
+                    counters.dropped(data.len() - written);
+                    return Err(io::ErrorKind::WriteZero.into());
+                },
+                Ok(bytes) => {
+                    written += bytes;
+                    stalled = None;
+                    counters.wrote(bytes);
+                },
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => {
+                    counters.stalled(*stalled.get_or_insert_with(Instant::now));
+
+                    if stop.is_disconnected() {
+                        return Ok(false);
+                    }
+                },
+                Err(e) => {
+                    counters.dropped(data.len() - written);
+                    return Err(e);
+                },
+            }
+        }
+
+        Ok(true)
     }
 
-    fn clear_buffer(port: &mut SerialPort) {
-        let mut buf = [0u8; 1];
+    /// Sends `data` byte by byte with the delays of `pacing`, waiting for an
+    /// echo or prompt after every complete line. With RS-485 the transceiver
+    /// is switched back to receiving for the wait. Returns `Ok(false)` if
+    /// `stop` came first.
+    fn write_paced(
+        transport: &dyn Transport,
+        data: &[u8],
+        pacing: &Pacing,
+        half_duplex: &HalfDuplex,
+        receive_watch: &ReceiveWatch,
+        counters: &TransmitCounters,
+        stop: &Receiver<()>,
+    ) -> io::Result<bool> {
+        let mut remaining = data.len();
+
+        for line in Self::lines(data) {
+            receive_watch.clear();
+
+            let sent = Self::with_transceiver(transport, half_duplex, || {
+                for byte in line {
+                    remaining -= 1;
+
+                    match Self::write_data(transport, std::slice::from_ref(byte), counters, stop) {
+                        Ok(true) => (),
+                        Ok(false) => return Ok(false),
+                        Err(e) => {
+                            counters.dropped(remaining);
+                            return Err(e);
+                        },
+                    }
+
+                    if !Self::sleep_unless_stopped(pacing.char_delay, stop) {
+                        return Ok(false);
+                    }
+                }
+
+                Ok(true)
+            })?;
+
+            if !sent {
+                return Ok(false);
+            }
+
+            let text = line.strip_suffix(b"\r\n")
+                .or_else(|| line.strip_suffix(b"\n\r"))
+                .or_else(|| line.strip_suffix(b"\r"))
+                .or_else(|| line.strip_suffix(b"\n"));
 
-        while port.read_exact(&mut buf).is_ok() {
+            let Some(text) = text else {
+                // Not a complete line, nothing to wait for yet.
+                continue;
+            };
+
+            if !Self::sleep_unless_stopped(pacing.line_delay, stop) {
+                return Ok(false);
+            }
+
+            let expected = match &pacing.line_wait {
+                LineWait::Nothing => continue,
+                LineWait::Echo => text,
+                LineWait::Prompt(prompt) => prompt.as_bytes(),
+            };
+
+            if !expected.is_empty() && !receive_watch.wait_for(expected, Pacing::WAIT_TIMEOUT, stop) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Writes the address byte and the data, switching parity once each has
+    /// left the port. Returns `Ok(false)` if `stop` came first.
+    fn write_addressed(
+        transport: &dyn Transport,
+        address: u8,
+        data: &[u8],
+        config: &SerialConfig,
+        counters: &TransmitCounters,
+        stop: &Receiver<()>,
+    ) -> io::Result<bool> {
+        let mut remaining = 1 + data.len();
+
+        for (parity, bytes) in [(Parity::Mark, std::slice::from_ref(&address)), (Parity::Space, data)] {
+            let switched = transport.flush().and_then(|_| transport.configure(&SerialConfig { parity, ..config.clone() }));
+
+            if let Err(e) = switched {
+                counters.dropped(remaining);
+                return Err(e);
+            }
+
+            remaining -= bytes.len();
+
+            match Self::write_data(transport, bytes, counters, stop) {
+                Ok(true) => (),
+                Ok(false) => return Ok(false),
+                Err(e) => {
+                    counters.dropped(remaining);
+                    return Err(e);
+                },
+            }
+        }
+
+        transport.flush()?;
+        transport.configure(config)?;
+        Ok(true)
+    }
+
+    /// Waits for `duration`, or returns `false` as soon as `stop` comes.
+    fn sleep_unless_stopped(duration: Duration, stop: &Receiver<()>) -> bool {
+        !matches!(stop.recv_timeout(duration), Err(RecvTimeoutError::Disconnected))
+    }
+
+    /// Splits after each line end, where `\r\n` and `\n\r` count as one.
+    fn lines(data: &[u8]) -> Vec<&[u8]> {
+        let mut lines = Vec::new();
+        let mut start = 0;
+
+        for (index, &byte) in data.iter().enumerate() {
+            let line_end = match (byte, data.get(index + 1)) {
+                (b'\r', Some(b'\n')) | (b'\n', Some(b'\r')) => false,
+                (b'\r' | b'\n', _) => true,
+                _ => false,
+            };
+
+            if line_end {
+                lines.push(&data[start..=index]);
+                start = index + 1;
+            }
+        }
+
+        if start < data.len() {
+            lines.push(&data[start..]);
+        }
+
+        lines
+    }
+
+    /// Hands RS-485 to the driver, or sets RTS to its receive level for
+    /// toggling in software.
+    fn apply_rs485(&self, transport: &dyn Transport, rs485: &Rs485) -> io::Result<()> {
+        let software_rts = if rs485.enabled {
+            match transport.set_rs485(rs485) {
+                Ok(()) => false,
+                Err(e) if e.kind() == io::ErrorKind::Unsupported && transport.capabilities().modem_lines => {
+                    transport.set_rts(!rs485.rts_on_send)?;
+                    true
+                },
+                Err(e) => return Err(e),
+            }
+        } else {
+            // The driver keeps RS-485 mode across close and open, so it is
+            // switched off explicitly in case an earlier session turned it on.
+            match transport.set_rs485(rs485) {
+                Err(e) if e.kind() != io::ErrorKind::Unsupported => return Err(e),
+                _ => false,
+            }
+        };
+
+        *self.half_duplex.write().unwrap() = HalfDuplex {
+            config: rs485.clone(),
+            software_rts,
+        };
+
+        Ok(())
+    }
+
+    /// Runs `write` with the transceiver switched to sending if RTS is
+    /// toggled in software. The delay after sending also covers what the
+    /// UART still shifts out once the driver's buffer is empty.
+    fn with_transceiver(transport: &dyn Transport, half_duplex: &HalfDuplex, write: impl FnOnce() -> io::Result<bool>) -> io::Result<bool> {
+        if !half_duplex.config.enabled || !half_duplex.software_rts {
+            return write();
+        }
+
+        let rs485 = &half_duplex.config;
+        transport.set_rts(rs485.rts_on_send)?;
+        thread::sleep(rs485.delay_before_send);
+
+        let result = write().and_then(|written| {
+            transport.flush()?;
+            Ok(written)
+        });
+
+        thread::sleep(rs485.delay_after_send);
+        transport.set_rts(!rs485.rts_on_send)?;
+        result
+    }
+
+    fn set_break_after_flush(transport: &dyn Transport, state: bool) -> std::io::Result<()> {
+        transport.flush()?;
+        transport.set_break(state)
+    }
 
+    fn with_transport<T>(&self, f: impl FnOnce(&dyn Transport) -> std::io::Result<T>) -> Result<T> {
+        match self.transport.read().unwrap().as_ref() {
+            Some(transport) => Ok(f(transport.as_ref())?),
+            None => Err(anyhow::anyhow!("port is not open")),
         }
     }
 }
@@ -0,0 +1,54 @@
+/// Incremental UTF-8 decoder for received data.
+///
+/// A multi-byte character can be split across two reads, so the trailing
+/// incomplete sequence is kept until the next chunk arrives. Invalid bytes
+/// are replaced with U+FFFD.
+#[derive(Debug, Default)]
+pub struct TextDecoder {
+    pending: Vec<u8>,
+}
+
+impl TextDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+
+        let mut text = String::new();
+        let mut rest = self.pending.as_slice();
+
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(s) => {
+                    text.push_str(s);
+                    rest = &[];
+                    break;
+                },
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &invalid[len..];
+                        },
+                        None => {
+                            rest = invalid;
+                            break;
+                        },
+                    }
+                },
+            }
+        }
+
+        self.pending = rest.to_vec();
+        text
+    }
+
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
@@ -0,0 +1,221 @@
+pub mod loopback;
+pub mod mock;
+pub mod rfc2217;
+pub mod serial_port;
+pub mod tcp;
+
+use std::io;
+
+use crate::port_match;
+use crate::serial::{Rs485, SerialConfig};
+
+pub use loopback::LoopbackTransport;
+pub use mock::{MockScript, MockTransport};
+pub use rfc2217::Rfc2217Transport;
+pub use serial_port::SerialPortTransport;
+pub use tcp::TcpTransport;
+
+/// Kind of connection selected by the device string, e.g. `tcp://host:port`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionType {
+    #[default]
+    Serial,
+    Tcp,
+    Rfc2217,
+    Loopback,
+    Mock,
+}
+
+impl ConnectionType {
+    pub const ALL: [ConnectionType; 5] = [
+        ConnectionType::Serial,
+        ConnectionType::Tcp,
+        ConnectionType::Rfc2217,
+        ConnectionType::Loopback,
+        ConnectionType::Mock,
+    ];
+
+    pub fn scheme(&self) -> Option<&'static str> {
+        match self {
+            ConnectionType::Serial => None,
+            ConnectionType::Tcp => Some("tcp"),
+            ConnectionType::Rfc2217 => Some("rfc2217"),
+            ConnectionType::Loopback => Some("loop"),
+            ConnectionType::Mock => Some("mock"),
+        }
+    }
+
+    /// Example of what follows the scheme in a device string.
+    pub fn address_hint(&self) -> &'static str {
+        match self {
+            ConnectionType::Serial => "",
+            ConnectionType::Tcp | ConnectionType::Rfc2217 => "host:port",
+            ConnectionType::Loopback => "?delay_ms=0",
+            ConnectionType::Mock => "script.toml",
+        }
+    }
+
+    /// Splits a device string into its connection type and address.
+    pub fn parse(device: &str) -> (Self, &str) {
+        match device.split_once("://") {
+            Some((scheme, address)) => Self::ALL
+                .into_iter()
+                .find(|connection_type| connection_type.scheme() == Some(scheme))
+                .map_or((ConnectionType::Serial, device), |connection_type| (connection_type, address)),
+            None => (ConnectionType::Serial, device),
+        }
+    }
+
+    pub fn device(&self, address: &str) -> String {
+        match self.scheme() {
+            Some(scheme) => format!("{scheme}://{address}"),
+            None => address.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionType::Serial => write!(f, "Serial"),
+            ConnectionType::Tcp => write!(f, "TCP"),
+            ConnectionType::Rfc2217 => write!(f, "RFC 2217"),
+            ConnectionType::Loopback => write!(f, "Loopback"),
+            ConnectionType::Mock => write!(f, "Mock"),
+        }
+    }
+}
+
+/// What a transport supports beyond plain reads and writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `configure` changes the line settings of the underlying UART.
+    pub configure: bool,
+    /// DTR/RTS can be driven and CTS/DSR/RI/CD read.
+    pub modem_lines: bool,
+    /// A BREAK condition can be asserted with `set_break`.
+    pub send_break: bool,
+    /// The driver counts receive errors, see `line_errors`.
+    pub line_errors: bool,
+}
+
+/// State of the modem input lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModemStatus {
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub cd: bool,
+}
+
+/// Stands in for a byte received with a parity or framing error when
+/// `SerialConfig::mark_errors` is set; ASCII SUB is meant for exactly that.
+pub const ERROR_SUBSTITUTE: u8 = 0x1a;
+
+/// Receive errors the UART driver counted since the port was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineErrors {
+    pub framing: u32,
+    pub parity: u32,
+    /// Bytes lost because the UART's receive FIFO was full.
+    pub overrun: u32,
+    /// Bytes lost because the driver's receive buffer was full.
+    pub buffer_overrun: u32,
+    pub breaks: u32,
+}
+
+impl LineErrors {
+    /// Corrupted or lost bytes; a BREAK is a line condition rather than an error.
+    pub fn total(&self) -> u32 {
+        self.framing + self.parity + self.overrun + self.buffer_overrun
+    }
+
+    /// Counts since `baseline` was read.
+    pub fn since(&self, baseline: &LineErrors) -> LineErrors {
+        LineErrors {
+            framing: self.framing.wrapping_sub(baseline.framing),
+            parity: self.parity.wrapping_sub(baseline.parity),
+            overrun: self.overrun.wrapping_sub(baseline.overrun),
+            buffer_overrun: self.buffer_overrun.wrapping_sub(baseline.buffer_overrun),
+            breaks: self.breaks.wrapping_sub(baseline.breaks),
+        }
+    }
+}
+
+/// A byte stream that `Serial` drives from its receive and transmit threads.
+///
+/// Both threads share the transport, so all methods take `&self`. `read`
+/// should return within a few milliseconds with `ErrorKind::TimedOut` when no
+/// data is available, so the receive thread can notice `stop`.
+pub trait Transport: Send + Sync {
+    fn open(name: &str, config: &SerialConfig) -> io::Result<Self>
+    where
+        Self: Sized;
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize>;
+
+    /// Blocks until written data has left the transport.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn configure(&self, _config: &SerialConfig) -> io::Result<()> {
+        Err(unsupported("configure"))
+    }
+
+    /// Reads back the line settings in effect.
+    fn settings(&self) -> io::Result<SerialConfig> {
+        Err(unsupported("reading back settings"))
+    }
+
+    fn close(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn set_dtr(&self, _state: bool) -> io::Result<()> {
+        Err(unsupported("DTR control"))
+    }
+
+    fn set_rts(&self, _state: bool) -> io::Result<()> {
+        Err(unsupported("RTS control"))
+    }
+
+    fn set_break(&self, _state: bool) -> io::Result<()> {
+        Err(unsupported("BREAK"))
+    }
+
+    fn modem_status(&self) -> io::Result<ModemStatus> {
+        Err(unsupported("modem status"))
+    }
+
+    fn line_errors(&self) -> io::Result<LineErrors> {
+        Err(unsupported("error counters"))
+    }
+
+    /// Lets the driver switch the transceiver for RS-485. Fails with
+    /// `ErrorKind::Unsupported` if it can't, so RTS has to be toggled instead.
+    fn set_rs485(&self, _config: &Rs485) -> io::Result<()> {
+        Err(unsupported("RS-485 mode"))
+    }
+}
+
+/// Opens the transport matching `device`.
+pub fn open(device: &str, config: &SerialConfig) -> io::Result<Box<dyn Transport>> {
+    Ok(match ConnectionType::parse(device) {
+        (ConnectionType::Serial, name) => Box::new(SerialPortTransport::open(&port_match::resolve_device(name)?, config)?),
+        (ConnectionType::Tcp, address) => Box::new(TcpTransport::open(address, config)?),
+        (ConnectionType::Rfc2217, address) => Box::new(Rfc2217Transport::open(address, config)?),
+        (ConnectionType::Loopback, options) => Box::new(LoopbackTransport::open(options, config)?),
+        (ConnectionType::Mock, path) => Box::new(MockTransport::open(path, config)?),
+    })
+}
+
+pub(crate) fn unsupported(operation: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("{operation} is not supported by this transport"))
+}
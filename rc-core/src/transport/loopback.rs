@@ -0,0 +1,133 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::serial::SerialConfig;
+use super::{Capabilities, ModemStatus, Transport};
+
+/// Data waiting to be read, each chunk released at its own time.
+#[derive(Default)]
+pub(crate) struct DelayQueue {
+    chunks: Mutex<VecDeque<(Instant, Vec<u8>)>>,
+    ready: Condvar,
+}
+
+impl DelayQueue {
+    const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+    pub(crate) fn push(&self, delay: Duration, data: Vec<u8>) {
+        self.chunks.lock().unwrap().push_back((Instant::now() + delay, data));
+        self.ready.notify_all();
+    }
+
+    pub(crate) fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + Self::READ_TIMEOUT;
+        let mut chunks = self.chunks.lock().unwrap();
+
+        loop {
+            let now = Instant::now();
+
+            if let Some((due, data)) = chunks.front_mut() {
+                if *due <= now {
+                    let read_bytes = data.len().min(buf.len());
+                    buf[..read_bytes].copy_from_slice(&data[..read_bytes]);
+                    data.drain(..read_bytes);
+
+                    if data.is_empty() {
+                        chunks.pop_front();
+                    }
+
+                    return Ok(read_bytes);
+                }
+            }
+
+            if now >= deadline {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+
+            let wake = chunks.front().map_or(deadline, |(due, _)| (*due).min(deadline));
+            chunks = self.ready.wait_timeout(chunks, wake - now).unwrap().0;
+        }
+    }
+}
+
+/// Echoes everything written to it, like a loopback plug with TX wired to RX,
+/// RTS to CTS and DTR to DSR and CD. `loop://?delay_ms=50` delays the echo.
+#[derive(Default)]
+pub struct LoopbackTransport {
+    delay: Duration,
+    queue: DelayQueue,
+    lines: Mutex<(bool, bool)>,
+}
+
+impl LoopbackTransport {
+    fn parse_delay(options: &str) -> io::Result<Duration> {
+        match options.strip_prefix("?delay_ms=") {
+            Some(delay) => delay
+                .parse()
+                .map(Duration::from_millis)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid loopback delay: {delay}"))),
+            None if options.is_empty() => Ok(Duration::ZERO),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown loopback options: {options}"))),
+        }
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn open(options: &str, _config: &SerialConfig) -> io::Result<Self> {
+        Ok(Self {
+            delay: Self::parse_delay(options)?,
+            ..Default::default()
+        })
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.queue.read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.queue.push(self.delay, buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn configure(&self, _config: &SerialConfig) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            configure: true,
+            modem_lines: true,
+            send_break: true,
+            line_errors: false,
+        }
+    }
+
+    fn set_dtr(&self, state: bool) -> io::Result<()> {
+        self.lines.lock().unwrap().0 = state;
+        Ok(())
+    }
+
+    fn set_rts(&self, state: bool) -> io::Result<()> {
+        self.lines.lock().unwrap().1 = state;
+        Ok(())
+    }
+
+    fn set_break(&self, _state: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn modem_status(&self) -> io::Result<ModemStatus> {
+        let (dtr, rts) = *self.lines.lock().unwrap();
+
+        Ok(ModemStatus {
+            cts: rts,
+            dsr: dtr,
+            ri: false,
+            cd: dtr,
+        })
+    }
+}
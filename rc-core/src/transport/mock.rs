@@ -0,0 +1,115 @@
+use serde::Deserialize;
+use std::{io, sync::Mutex, time::Duration};
+
+use crate::serial::SerialConfig;
+use super::{loopback::DelayQueue, Transport};
+
+/// Canned device behaviour loaded from a TOML file:
+///
+/// ```toml
+/// greeting = "U-Boot 2023.04\r\n=> "
+/// delay_ms = 20
+///
+/// [[response]]
+/// expect = "version\r"
+/// reply = "U-Boot 2023.04\r\n=> "
+/// delay_ms = 100
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct MockScript {
+    /// Sent once, right after the port is opened.
+    pub greeting: Option<String>,
+    /// Delay for replies that don't set their own.
+    pub delay_ms: u64,
+    /// Echo written data back before replying.
+    pub echo: bool,
+    #[serde(rename = "response")]
+    pub responses: Vec<MockResponse>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockResponse {
+    pub expect: String,
+    pub reply: String,
+    pub delay_ms: Option<u64>,
+}
+
+impl MockScript {
+    pub fn parse(script: &str) -> io::Result<Self> {
+        toml::from_str(script).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+/// Replays a `MockScript`: every time the written data contains an `expect`
+/// string, the matching `reply` is queued after its delay.
+pub struct MockTransport {
+    script: MockScript,
+    queue: DelayQueue,
+    received: Mutex<Vec<u8>>,
+}
+
+impl MockTransport {
+    pub fn from_script(script: MockScript) -> Self {
+        let transport = Self {
+            script,
+            queue: DelayQueue::default(),
+            received: Mutex::new(Vec::new()),
+        };
+
+        if let Some(greeting) = &transport.script.greeting {
+            transport.queue.push(Duration::from_millis(transport.script.delay_ms), greeting.as_bytes().to_vec());
+        }
+
+        transport
+    }
+
+    fn find_match(&self, received: &[u8]) -> Option<(usize, &MockResponse)> {
+        self.script
+            .responses
+            .iter()
+            .filter(|response| !response.expect.is_empty())
+            .filter_map(|response| {
+                received
+                    .windows(response.expect.len())
+                    .position(|window| window == response.expect.as_bytes())
+                    .map(|position| (position + response.expect.len(), response))
+            })
+            .min_by_key(|(end, _)| *end)
+    }
+}
+
+impl Transport for MockTransport {
+    fn open(path: &str, _config: &SerialConfig) -> io::Result<Self> {
+        Ok(Self::from_script(MockScript::parse(&std::fs::read_to_string(path)?)?))
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.queue.read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        if self.script.echo {
+            self.queue.push(Duration::ZERO, buf.to_vec());
+        }
+
+        let mut received = self.received.lock().unwrap();
+        received.extend_from_slice(buf);
+
+        while let Some((end, response)) = self.find_match(&received) {
+            let delay = response.delay_ms.unwrap_or(self.script.delay_ms);
+            self.queue.push(Duration::from_millis(delay), response.reply.as_bytes().to_vec());
+            received.drain(..end);
+        }
+
+        let longest = self.script.responses.iter().map(|response| response.expect.len()).max().unwrap_or(0);
+        let excess = received.len().saturating_sub(longest);
+        received.drain(..excess);
+
+        Ok(buf.len())
+    }
+
+    fn configure(&self, _config: &SerialConfig) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+use serial2::{CharSize, FlowControl, StopBits};
+use std::{
+    io,
+    net::{Shutdown, TcpStream},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::rfc2217::{self, command, control, Event, Telnet};
+use crate::serial::{Parity, SerialConfig};
+use super::{Capabilities, ModemStatus, TcpTransport, Transport};
+
+/// Telnet COM Port Control client: a TCP connection whose remote UART is
+/// configured through RFC 2217 sub-negotiations.
+pub struct Rfc2217Transport {
+    stream: TcpStream,
+    writer: Mutex<()>,
+    /// Held from reading until decoding is done, since telnet sequences can
+    /// span reads and `configure` reads alongside the receive thread.
+    reader: Mutex<()>,
+    state: Mutex<ClientState>,
+}
+
+#[derive(Default)]
+struct ClientState {
+    telnet: Telnet,
+    modem_state: u8,
+    /// The settings last asked for, of which the server sets only the line settings.
+    requested: SerialConfig,
+    acknowledged: Acknowledged,
+    /// Data that arrived while waiting for acknowledgements, for `read`.
+    received: Vec<u8>,
+}
+
+/// Line settings as the server reported them, `None` until it has.
+#[derive(Default)]
+struct Acknowledged {
+    baudrate: Option<u32>,
+    char_size: Option<CharSize>,
+    parity: Option<Parity>,
+    stop_bits: Option<StopBits>,
+    flow_control: Option<FlowControl>,
+}
+
+impl Acknowledged {
+    fn missing(&self) -> Vec<&'static str> {
+        [
+            ("baud rate", self.baudrate.is_none()),
+            ("char size", self.char_size.is_none()),
+            ("parity", self.parity.is_none()),
+            ("stop bits", self.stop_bits.is_none()),
+            ("flow control", self.flow_control.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(name, missing)| missing.then_some(name))
+        .collect()
+    }
+}
+
+impl Rfc2217Transport {
+    const OPTIONS: [u8; 3] = [rfc2217::BINARY, rfc2217::SUPPRESS_GO_AHEAD, rfc2217::COM_PORT_OPTION];
+
+    /// How long `open` and `configure` wait for the server to acknowledge.
+    const ACKNOWLEDGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Line settings as last acknowledged by the server. Fails while it has
+    /// not acknowledged all of them.
+    pub fn remote_config(&self) -> io::Result<SerialConfig> {
+        let state = self.state.lock().unwrap();
+        let acknowledged = &state.acknowledged;
+
+        match (acknowledged.baudrate, acknowledged.char_size, acknowledged.parity, acknowledged.stop_bits, acknowledged.flow_control) {
+            (Some(baudrate), Some(char_size), Some(parity), Some(stop_bits), Some(flow_control)) => Ok(SerialConfig {
+                baudrate,
+                char_size,
+                parity,
+                stop_bits,
+                flow_control,
+                ..state.requested.clone()
+            }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("server did not acknowledge the {}", acknowledged.missing().join(", ")),
+            )),
+        }
+    }
+
+    /// Reads once from the server, keeping data for `read` and applying the rest.
+    fn receive(&self) -> io::Result<()> {
+        let _reader = self.reader.lock().unwrap();
+
+        let mut raw = [0u8; 1000];
+        let read_bytes = TcpTransport::read_stream(&self.stream, &mut raw)?;
+
+        let mut data = Vec::with_capacity(read_bytes);
+        let mut events = Vec::new();
+
+        let mut state = self.state.lock().unwrap();
+        state.telnet.decode(&raw[..read_bytes], &mut data, &mut events);
+        state.received.extend_from_slice(&data);
+
+        for event in events {
+            self.handle_event(&mut state, event)?;
+        }
+
+        Ok(())
+    }
+
+    /// Gives the server a moment to answer the line settings just sent.
+    fn wait_for_acknowledgement(&self) {
+        let deadline = Instant::now() + Self::ACKNOWLEDGE_TIMEOUT;
+
+        while !self.state.lock().unwrap().acknowledged.missing().is_empty() && Instant::now() < deadline {
+            match self.receive() {
+                Err(e) if e.kind() != io::ErrorKind::TimedOut => break,
+                _ => (),
+            }
+        }
+    }
+
+    fn send_raw(&self, frame: &[u8]) -> io::Result<()> {
+        let _writer = self.writer.lock().unwrap();
+        TcpTransport::write_frame(&self.stream, frame)
+    }
+
+    fn send_command(&self, command: u8, value: &[u8]) -> io::Result<()> {
+        self.send_raw(&rfc2217::com_port_command(command, value))
+    }
+
+    fn set_control(&self, value: u8) -> io::Result<()> {
+        self.send_command(command::SET_CONTROL, &[value])
+    }
+
+    fn handle_event(&self, state: &mut ClientState, event: Event) -> io::Result<()> {
+        match event {
+            Event::Do(option) if !Self::OPTIONS.contains(&option) => {
+                self.send_raw(&rfc2217::negotiation(rfc2217::WONT, option))
+            },
+            Event::Will(option) if !Self::OPTIONS.contains(&option) => {
+                self.send_raw(&rfc2217::negotiation(rfc2217::DONT, option))
+            },
+            Event::Subnegotiation(rfc2217::COM_PORT_OPTION, payload) => {
+                Self::handle_response(state, &payload);
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_response(state: &mut ClientState, payload: &[u8]) {
+        let Some((&code, value)) = payload.split_first() else {
+            return;
+        };
+
+        let Some(response) = code.checked_sub(command::SERVER_OFFSET) else {
+            return;
+        };
+
+        match (response, value) {
+            (command::SET_BAUDRATE, &[a, b, c, d]) => {
+                let baudrate = u32::from_be_bytes([a, b, c, d]);
+
+                if baudrate != 0 {
+                    state.acknowledged.baudrate = Some(baudrate);
+                }
+            },
+            (command::SET_DATASIZE, &[value]) => {
+                if let Some(char_size) = rfc2217::char_size(value) {
+                    state.acknowledged.char_size = Some(char_size);
+                }
+            },
+            (command::SET_PARITY, &[value]) => {
+                if let Some(parity) = rfc2217::parity(value) {
+                    state.acknowledged.parity = Some(parity);
+                }
+            },
+            (command::SET_STOPSIZE, &[value]) => {
+                if let Some(stop_bits) = rfc2217::stop_bits(value) {
+                    state.acknowledged.stop_bits = Some(stop_bits);
+                }
+            },
+            (command::SET_CONTROL, &[value]) => {
+                if let Some(flow_control) = rfc2217::flow_control(value) {
+                    state.acknowledged.flow_control = Some(flow_control);
+                }
+            },
+            (command::NOTIFY_MODEMSTATE, &[value]) => state.modem_state = value,
+            _ => (),
+        }
+    }
+}
+
+impl Transport for Rfc2217Transport {
+    fn open(address: &str, config: &SerialConfig) -> io::Result<Self> {
+        let transport = Self {
+            stream: TcpTransport::connect(address)?,
+            writer: Mutex::new(()),
+            reader: Mutex::new(()),
+            state: Mutex::default(),
+        };
+
+        let mut negotiation = Vec::new();
+        for option in Self::OPTIONS {
+            negotiation.extend(rfc2217::negotiation(rfc2217::WILL, option));
+            negotiation.extend(rfc2217::negotiation(rfc2217::DO, option));
+        }
+        transport.send_raw(&negotiation)?;
+
+        transport.send_command(command::SET_MODEMSTATE_MASK, &[0xff])?;
+        transport.configure(config)?;
+
+        Ok(transport)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.state.lock().unwrap().received.is_empty() {
+            self.receive()?;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let read_bytes = state.received.len().min(buf.len());
+
+        if read_bytes == 0 {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        buf[..read_bytes].copy_from_slice(&state.received[..read_bytes]);
+        state.received.drain(..read_bytes);
+        Ok(read_bytes)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.send_raw(&rfc2217::escape(buf))?;
+        Ok(buf.len())
+    }
+
+    fn configure(&self, config: &SerialConfig) -> io::Result<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.requested = config.clone();
+            state.acknowledged = Acknowledged::default();
+        }
+
+        self.send_command(command::SET_BAUDRATE, &config.baudrate.to_be_bytes())?;
+        self.send_command(command::SET_DATASIZE, &[rfc2217::data_size(config.char_size)])?;
+        self.send_command(command::SET_PARITY, &[rfc2217::parity_value(config.parity)])?;
+        self.send_command(command::SET_STOPSIZE, &[rfc2217::stop_size(config.stop_bits)])?;
+        self.set_control(rfc2217::flow_control_value(config.flow_control))?;
+
+        self.wait_for_acknowledgement();
+        Ok(())
+    }
+
+    fn settings(&self) -> io::Result<SerialConfig> {
+        self.remote_config()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            configure: true,
+            modem_lines: true,
+            send_break: true,
+            line_errors: false,
+        }
+    }
+
+    fn set_dtr(&self, state: bool) -> io::Result<()> {
+        self.set_control(if state { control::DTR_ON } else { control::DTR_OFF })
+    }
+
+    fn set_rts(&self, state: bool) -> io::Result<()> {
+        self.set_control(if state { control::RTS_ON } else { control::RTS_OFF })
+    }
+
+    fn set_break(&self, state: bool) -> io::Result<()> {
+        self.set_control(if state { control::BREAK_ON } else { control::BREAK_OFF })
+    }
+
+    fn modem_status(&self) -> io::Result<ModemStatus> {
+        Ok(rfc2217::modem_status(self.state.lock().unwrap().modem_state))
+    }
+}
@@ -0,0 +1,436 @@
+use serial2::{IntoSettings, SerialPort};
+use std::{io::{self, Read}, sync::{Mutex, RwLock}, time::Duration};
+
+use crate::port_lock::{self, LockFile};
+use crate::serial::{Parity, SerialConfig};
+use super::{Capabilities, ModemStatus, Transport, ERROR_SUBSTITUTE};
+
+pub struct SerialPortTransport {
+    port: RwLock<SerialPort>,
+    /// `None` unless the driver marks bytes received with errors.
+    error_marks: Mutex<Option<MarkState>>,
+    exclusive: bool,
+    _lock_file: Option<LockFile>,
+}
+
+/// Position within a mark, which may be split across reads: the driver puts
+/// `\xff\x00` before a byte received with an error, and doubles real `\xff` bytes.
+#[derive(Debug, Clone, Copy, Default)]
+enum MarkState {
+    #[default]
+    Data,
+    Escape,
+    Marked,
+}
+
+impl SerialPortTransport {
+    fn clear_buffer(port: &mut SerialPort) {
+        let mut buf = [0u8; 1];
+
+        while port.read_exact(&mut buf).is_ok() {
+
+        }
+    }
+
+    /// Mark and space parity need CMSPAR, which serial2 doesn't know about.
+    /// Cleared otherwise, in case the last program using the port left it set.
+    fn set_stick_parity(port: &SerialPort, parity: Parity) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        return termios::set_stick_parity(std::os::unix::io::AsRawFd::as_raw_fd(port), parity.is_stick());
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = port;
+
+            match parity.is_stick() {
+                true => Err(super::unsupported("mark and space parity")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn set_error_marking(&self, port: &SerialPort, enabled: bool) -> io::Result<()> {
+        #[cfg(unix)]
+        termios::set_error_marking(std::os::unix::io::AsRawFd::as_raw_fd(port), enabled)?;
+
+        #[cfg(not(unix))]
+        let _ = port;
+
+        let mut error_marks = self.error_marks.lock().unwrap();
+
+        if enabled != error_marks.is_some() {
+            *error_marks = enabled.then(MarkState::default);
+        }
+
+        Ok(())
+    }
+
+    /// Turns the error marks in the first `length` bytes of `buf` into
+    /// `ERROR_SUBSTITUTE` in place and returns the length of the result.
+    pub(crate) fn unmark(&self, buf: &mut [u8], length: usize) -> usize {
+        let mut error_marks = self.error_marks.lock().unwrap();
+
+        let Some(state) = error_marks.as_mut() else {
+            return length;
+        };
+
+        let mut unmarked = 0;
+
+        for index in 0..length {
+            let byte = buf[index];
+
+            let output = match (*state, byte) {
+                (MarkState::Data, 0xff) => {
+                    *state = MarkState::Escape;
+                    None
+                },
+                (MarkState::Data, _) => Some(byte),
+                (MarkState::Escape, 0x00) => {
+                    *state = MarkState::Marked;
+                    None
+                },
+                (MarkState::Escape, _) => {
+                    *state = MarkState::Data;
+                    Some(byte)
+                },
+                // The byte itself is unreliable; a BREAK shows up as a marked zero.
+                (MarkState::Marked, _) => {
+                    *state = MarkState::Data;
+                    Some(ERROR_SUBSTITUTE)
+                },
+            };
+
+            if let Some(byte) = output {
+                buf[unmarked] = byte;
+                unmarked += 1;
+            }
+        }
+
+        unmarked
+    }
+}
+
+impl Transport for SerialPortTransport {
+    fn open(name: &str, config: &SerialConfig) -> io::Result<Self> {
+        let lock_file = if config.exclusive { LockFile::acquire(name)? } else { None };
+
+        // Our own lock file has to go before looking for who holds the port.
+        let mut port = match SerialPort::open(name, config.clone()) {
+            Ok(port) => port,
+            Err(e) => {
+                drop(lock_file);
+                return Err(port_lock::describe_busy(name, e));
+            },
+        };
+
+        #[cfg(unix)]
+        if config.exclusive {
+            if let Err(e) = port_lock::lock_exclusive(std::os::unix::io::AsRawFd::as_raw_fd(&port)) {
+                drop((port, lock_file));
+                return Err(port_lock::describe_busy(name, e));
+            }
+        }
+
+        Self::set_stick_parity(&port, config.parity)?;
+        port.set_read_timeout(Duration::from_millis(10))?;
+        port.discard_buffers()?;
+
+        Self::clear_buffer(&mut port);
+
+        let transport = Self {
+            port: RwLock::new(port),
+            error_marks: Mutex::new(None),
+            exclusive: config.exclusive,
+            _lock_file: lock_file,
+        };
+
+        if config.mark_errors {
+            transport.set_error_marking(&transport.port.read().unwrap(), true)?;
+        }
+
+        Ok(transport)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.port.read().unwrap().read(buf) {
+            Ok(0) if !buf.is_empty() => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "device hung up")),
+            Ok(read) => match self.unmark(buf, read) {
+                // Only the start of a mark; the rest comes with the next read.
+                0 => Err(io::ErrorKind::TimedOut.into()),
+                unmarked => Ok(unmarked),
+            },
+            result => result,
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.port.read().unwrap().write(buf)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.port.read().unwrap().flush()
+    }
+
+    fn configure(&self, config: &SerialConfig) -> io::Result<()> {
+        let mut port = self.port.write().unwrap();
+        let mut settings = port.get_configuration()?;
+        config.clone().apply_to_settings(&mut settings)?;
+        port.set_configuration(&settings)?;
+        Self::set_stick_parity(&port, config.parity)?;
+        self.set_error_marking(&port, config.mark_errors)
+    }
+
+    fn settings(&self) -> io::Result<SerialConfig> {
+        let port = self.port.read().unwrap();
+        let settings = port.get_configuration()?;
+
+        #[cfg(target_os = "linux")]
+        let stick = termios::stick_parity(std::os::unix::io::AsRawFd::as_raw_fd(&*port))?;
+
+        #[cfg(not(target_os = "linux"))]
+        let stick = false;
+
+        let parity = match (Parity::from(settings.get_parity()?), stick) {
+            (Parity::Odd, true) => Parity::Mark,
+            (Parity::Even, true) => Parity::Space,
+            (parity, _) => parity,
+        };
+
+        Ok(SerialConfig {
+            baudrate: settings.get_baud_rate()?,
+            char_size: settings.get_char_size()?,
+            parity,
+            flow_control: settings.get_flow_control()?,
+            stop_bits: settings.get_stop_bits()?,
+            ..Default::default()
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            configure: true,
+            modem_lines: true,
+            send_break: cfg!(unix),
+            line_errors: cfg!(target_os = "linux"),
+        }
+    }
+
+    fn set_dtr(&self, state: bool) -> io::Result<()> {
+        self.port.read().unwrap().set_dtr(state)
+    }
+
+    fn set_rts(&self, state: bool) -> io::Result<()> {
+        self.port.read().unwrap().set_rts(state)
+    }
+
+    #[cfg(unix)]
+    fn set_break(&self, state: bool) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let request = if state { libc::TIOCSBRK } else { libc::TIOCCBRK };
+
+        if unsafe { libc::ioctl(self.port.read().unwrap().as_raw_fd(), request) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn modem_status(&self) -> io::Result<ModemStatus> {
+        let port = self.port.read().unwrap();
+
+        Ok(ModemStatus {
+            cts: port.read_cts()?,
+            dsr: port.read_dsr()?,
+            ri: port.read_ri()?,
+            cd: port.read_cd()?,
+        })
+    }
+
+    #[cfg(all(target_os = "linux", not(target_arch = "sparc64")))]
+    fn set_rs485(&self, config: &crate::serial::Rs485) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        termios::set_rs485(self.port.read().unwrap().as_raw_fd(), config)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn line_errors(&self) -> io::Result<super::LineErrors> {
+        use std::os::unix::io::AsRawFd;
+
+        let mut counters = termios::SerialIcounter::default();
+
+        if unsafe { libc::ioctl(self.port.read().unwrap().as_raw_fd(), libc::TIOCGICOUNT, &mut counters) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The kernel counts in ints that only ever grow and may wrap.
+        Ok(super::LineErrors {
+            framing: counters.frame as u32,
+            parity: counters.parity as u32,
+            overrun: counters.overrun as u32,
+            buffer_overrun: counters.buf_overrun as u32,
+            breaks: counters.brk as u32,
+        })
+    }
+}
+
+impl Drop for SerialPortTransport {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.exclusive {
+            port_lock::unlock_exclusive(std::os::unix::io::AsRawFd::as_raw_fd(self));
+        }
+    }
+}
+
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for SerialPortTransport {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.port.read().unwrap().as_raw_fd()
+    }
+}
+
+#[cfg(unix)]
+mod termios {
+    use std::io;
+    use std::os::unix::io::RawFd;
+
+    /// `struct serial_icounter_struct` from `<linux/serial.h>`, which libc lacks.
+    #[cfg(target_os = "linux")]
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct SerialIcounter {
+        pub cts: libc::c_int,
+        pub dsr: libc::c_int,
+        pub rng: libc::c_int,
+        pub dcd: libc::c_int,
+        pub rx: libc::c_int,
+        pub tx: libc::c_int,
+        pub frame: libc::c_int,
+        pub overrun: libc::c_int,
+        pub parity: libc::c_int,
+        pub brk: libc::c_int,
+        pub buf_overrun: libc::c_int,
+        pub reserved: [libc::c_int; 9],
+    }
+
+    /// `struct serial_rs485` from `<linux/serial.h>`.
+    #[cfg(all(target_os = "linux", not(target_arch = "sparc64")))]
+    #[repr(C)]
+    #[derive(Default)]
+    struct SerialRs485 {
+        flags: u32,
+        delay_rts_before_send: u32,
+        delay_rts_after_send: u32,
+        padding: [u32; 5],
+    }
+
+    #[cfg(all(target_os = "linux", not(target_arch = "sparc64")))]
+    const SER_RS485_ENABLED: u32 = 1 << 0;
+    #[cfg(all(target_os = "linux", not(target_arch = "sparc64")))]
+    const SER_RS485_RTS_ON_SEND: u32 = 1 << 1;
+    #[cfg(all(target_os = "linux", not(target_arch = "sparc64")))]
+    const SER_RS485_RTS_AFTER_SEND: u32 = 1 << 2;
+    #[cfg(all(target_os = "linux", not(target_arch = "sparc64")))]
+    const SER_RS485_RX_DURING_TX: u32 = 1 << 4;
+
+    /// Switches the driver's RS-485 mode, keeping flags we don't know about,
+    /// such as bus termination. Drivers without RS-485 support report `Unsupported`.
+    #[cfg(all(target_os = "linux", not(target_arch = "sparc64")))]
+    pub fn set_rs485(fd: RawFd, config: &crate::serial::Rs485) -> io::Result<()> {
+        let mut rs485 = SerialRs485::default();
+        check(unsafe { libc::ioctl(fd, libc::TIOCGRS485, &mut rs485) }).map_err(unsupported)?;
+
+        let known = SER_RS485_ENABLED | SER_RS485_RTS_ON_SEND | SER_RS485_RTS_AFTER_SEND | SER_RS485_RX_DURING_TX;
+        rs485.flags &= !known;
+
+        if config.enabled {
+            rs485.flags |= SER_RS485_ENABLED;
+            rs485.flags |= if config.rts_on_send { SER_RS485_RTS_ON_SEND } else { SER_RS485_RTS_AFTER_SEND };
+
+            if config.rx_during_tx {
+                rs485.flags |= SER_RS485_RX_DURING_TX;
+            }
+        }
+
+        rs485.delay_rts_before_send = config.delay_before_send.as_millis().try_into().unwrap_or(u32::MAX);
+        rs485.delay_rts_after_send = config.delay_after_send.as_millis().try_into().unwrap_or(u32::MAX);
+
+        check(unsafe { libc::ioctl(fd, libc::TIOCSRS485, &rs485) }).map_err(unsupported)
+    }
+
+    #[cfg(all(target_os = "linux", not(target_arch = "sparc64")))]
+    fn unsupported(error: io::Error) -> io::Error {
+        match error.raw_os_error() {
+            Some(libc::ENOTTY | libc::EINVAL | libc::EOPNOTSUPP) => io::Error::new(io::ErrorKind::Unsupported, error),
+            _ => error,
+        }
+    }
+
+    /// Makes the driver check parity and mark bytes received with parity or
+    /// framing errors instead of passing them on as if they were fine.
+    pub fn set_error_marking(fd: RawFd, enabled: bool) -> io::Result<()> {
+        let mut termios = get(fd)?;
+
+        if enabled {
+            termios.c_iflag |= libc::INPCK | libc::PARMRK;
+            termios.c_iflag &= !(libc::IGNPAR | libc::ISTRIP);
+        } else {
+            termios.c_iflag &= !(libc::INPCK | libc::PARMRK);
+        }
+
+        set(fd, &termios)
+    }
+
+    /// Makes the odd or even parity bit always set or always clear.
+    #[cfg(target_os = "linux")]
+    pub fn set_stick_parity(fd: RawFd, enabled: bool) -> io::Result<()> {
+        let mut termios = get(fd)?;
+
+        if enabled == (termios.c_cflag & libc::CMSPAR != 0) {
+            return Ok(());
+        }
+
+        termios.c_cflag ^= libc::CMSPAR;
+        set(fd, &termios)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn stick_parity(fd: RawFd) -> io::Result<bool> {
+        Ok(get(fd)?.c_cflag & libc::CMSPAR != 0)
+    }
+
+    // Linux sets custom baud rates through termios2, which the plain termios
+    // calls would lose. Mirrors what serial2 does.
+    #[cfg(all(target_os = "linux", not(any(target_arch = "powerpc", target_arch = "powerpc64"))))]
+    fn get(fd: RawFd) -> io::Result<libc::termios2> {
+        let mut termios: libc::termios2 = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::ioctl(fd, libc::TCGETS2, &mut termios) })?;
+        Ok(termios)
+    }
+
+    #[cfg(all(target_os = "linux", not(any(target_arch = "powerpc", target_arch = "powerpc64"))))]
+    fn set(fd: RawFd, termios: &libc::termios2) -> io::Result<()> {
+        check(unsafe { libc::ioctl(fd, libc::TCSETS2, termios) })
+    }
+
+    #[cfg(not(all(target_os = "linux", not(any(target_arch = "powerpc", target_arch = "powerpc64")))))]
+    fn get(fd: RawFd) -> io::Result<libc::termios> {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::tcgetattr(fd, &mut termios) })?;
+        Ok(termios)
+    }
+
+    #[cfg(not(all(target_os = "linux", not(any(target_arch = "powerpc", target_arch = "powerpc64")))))]
+    fn set(fd: RawFd, termios: &libc::termios) -> io::Result<()> {
+        check(unsafe { libc::tcsetattr(fd, libc::TCSANOW, termios) })
+    }
+
+    fn check(result: libc::c_int) -> io::Result<()> {
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
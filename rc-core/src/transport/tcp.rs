@@ -0,0 +1,85 @@
+use std::{io::{self, Read, Write}, net::{Shutdown, TcpStream, ToSocketAddrs}, time::Duration};
+
+use crate::serial::SerialConfig;
+use super::Transport;
+
+/// Raw TCP client, e.g. a ser2net or terminal server port.
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Writes to a peer that stops reading give up after this long, so the
+    /// transmit thread gets to check for `stop`.
+    const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+    pub(crate) fn connect(address: &str) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, format!("{address} did not resolve to any address"));
+
+        for socket_address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&socket_address, Self::CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    stream.set_read_timeout(Some(Duration::from_millis(10)))?;
+                    stream.set_write_timeout(Some(Self::WRITE_TIMEOUT))?;
+                    return Ok(stream);
+                },
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    pub(crate) fn read_stream(stream: &TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        match (&*stream).read(buf) {
+            Ok(0) => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "connection closed by peer")),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::ErrorKind::TimedOut.into()),
+            result => result,
+        }
+    }
+
+    /// Writes all of `frame`, which must not be cut short, such as a telnet
+    /// command. Fails with `TimedOut` only while none of it has gone out.
+    pub(crate) fn write_frame(stream: &TcpStream, frame: &[u8]) -> io::Result<()> {
+        let mut written = 0;
+
+        while written < frame.len() {
+            match (&*stream).write(&frame[written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(bytes) => written += bytes,
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) && written == 0 => {
+                    return Err(io::ErrorKind::TimedOut.into());
+                },
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Transport for TcpTransport {
+    fn open(address: &str, _config: &SerialConfig) -> io::Result<Self> {
+        Ok(Self {
+            stream: Self::connect(address)?,
+        })
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        Self::read_stream(&self.stream, buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        match (&self.stream).write(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::ErrorKind::TimedOut.into()),
+            result => result,
+        }
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "tokio")]
+
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+use rc_core::async_serial::AsyncSerial;
+use rc_core::serial::SerialConfig;
+
+#[tokio::test]
+async fn loopback_echoes_through_blocking_fallback() {
+    let mut serial = AsyncSerial::open("loop://", &SerialConfig::default()).await.unwrap();
+
+    serial.write_all(b"ping\n").await.unwrap();
+    serial.flush().await.unwrap();
+
+    let mut received = [0u8; 5];
+    timeout(Duration::from_secs(2), serial.read_exact(&mut received)).await.unwrap().unwrap();
+    assert_eq!(&received, b"ping\n");
+}
+
+#[tokio::test]
+async fn tcp_reads_until_peer_closes() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0u8; 3];
+        stream.read_exact(&mut request).await.unwrap();
+        stream.write_all(b"OK\r\n").await.unwrap();
+    });
+
+    let mut serial = AsyncSerial::open(&format!("tcp://{address}"), &SerialConfig::default()).await.unwrap();
+    serial.write_all(b"AT\r").await.unwrap();
+
+    let mut received = Vec::new();
+    timeout(Duration::from_secs(2), serial.read_to_end(&mut received)).await.unwrap().unwrap();
+    assert_eq!(received, b"OK\r\n");
+
+    server.await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn pty_is_driven_by_the_reactor() {
+    let pty = rc_core::pty::Pty::open().unwrap();
+    let mut serial = AsyncSerial::open(pty.path(), &SerialConfig::default()).await.unwrap();
+
+    pty.write(b"boot\r\n");
+
+    let mut received = [0u8; 6];
+    timeout(Duration::from_secs(2), serial.read_exact(&mut received)).await.unwrap().unwrap();
+    assert_eq!(&received, b"boot\r\n");
+
+    serial.write_all(b"reset\r").await.unwrap();
+
+    let mut echoed = Vec::new();
+
+    for _ in 0..200 {
+        if let Some(data) = pty.try_recv() {
+            echoed.extend(data);
+        }
+
+        if echoed.len() >= 6 {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(echoed, b"reset\r");
+}
@@ -0,0 +1,53 @@
+use rc_core::port_match::PortMatch;
+use rc_core::serial::{PortInfo, PortType};
+
+fn usb_port(name: &str, vid: u16, pid: u16, serial_number: &str, product: &str) -> PortInfo {
+    PortInfo {
+        name: name.to_string(),
+        port_type: PortType::Usb,
+        vid: Some(vid),
+        pid: Some(pid),
+        serial_number: Some(serial_number.to_string()),
+        manufacturer: None,
+        product: Some(product.to_string()),
+    }
+}
+
+fn ports() -> Vec<PortInfo> {
+    vec![
+        usb_port("/dev/ttyUSB0", 0x0403, 0x6001, "FT12345", "FT232R USB UART"),
+        usb_port("/dev/ttyUSB1", 0x10c4, 0xea60, "0001", "CP2102 USB to UART Bridge Controller"),
+        usb_port("/dev/ttyUSB2", 0x10c4, 0xea60, "0002", "CP2102 USB to UART Bridge Controller"),
+    ]
+}
+
+fn rule(device: &str) -> PortMatch {
+    PortMatch::parse(device).expect("not a rule").expect("invalid rule")
+}
+
+#[test]
+fn plain_paths_are_not_rules() {
+    assert!(PortMatch::parse("/dev/ttyUSB0").is_none());
+    assert!(PortMatch::parse("COM3").is_none());
+}
+
+#[test]
+fn resolves_a_single_match() {
+    assert_eq!(rule("usb:0403:6001").resolve(&ports()).unwrap(), "/dev/ttyUSB0");
+    assert_eq!(rule("serial:0002").resolve(&ports()).unwrap(), "/dev/ttyUSB2");
+    assert_eq!(rule("product:\"ft232*\"").resolve(&ports()).unwrap(), "/dev/ttyUSB0");
+}
+
+#[test]
+fn reports_zero_and_multiple_matches() {
+    let error = rule("serial:NOPE").resolve(&ports()).unwrap_err();
+    assert!(error.contains("No port matches serial:NOPE"), "{error}");
+
+    let error = rule("product:CP2102*").resolve(&ports()).unwrap_err();
+    assert!(error.contains("/dev/ttyUSB1, /dev/ttyUSB2"), "{error}");
+}
+
+#[test]
+fn rejects_malformed_usb_ids() {
+    assert!(PortMatch::parse("usb:xyz:6001").unwrap().is_err());
+}
@@ -0,0 +1,152 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rc_core::bridge::Bridge;
+use rc_core::rfc2217::{self, command, control, modem_state, Event, Telnet};
+use rc_core::serial::SerialConfig;
+use rc_core::transport::{Rfc2217Transport, Transport};
+
+/// Minimal RFC 2217 server: acknowledges every COM-PORT-OPTION command,
+/// reports CTS and echoes data back.
+fn stand_in_server(mut stream: TcpStream) {
+    let mut telnet = Telnet::new();
+    let mut buf = [0u8; 256];
+
+    stream.write_all(&rfc2217::com_port_command(command::NOTIFY_MODEMSTATE + command::SERVER_OFFSET, &[modem_state::CTS])).unwrap();
+
+    while let Ok(read_bytes) = stream.read(&mut buf) {
+        if read_bytes == 0 {
+            break;
+        }
+
+        let mut data = Vec::new();
+        let mut events = Vec::new();
+        telnet.decode(&buf[..read_bytes], &mut data, &mut events);
+
+        for event in events {
+            if let Event::Subnegotiation(rfc2217::COM_PORT_OPTION, payload) = event {
+                let mut response = rfc2217::com_port_command(payload[0] + command::SERVER_OFFSET, &payload[1..]);
+
+                if payload[0] == command::SET_BAUDRATE {
+                    response = rfc2217::com_port_command(command::SET_BAUDRATE + command::SERVER_OFFSET, &57600u32.to_be_bytes());
+                }
+
+                stream.write_all(&response).unwrap();
+            }
+        }
+
+        stream.write_all(&rfc2217::escape(&data)).unwrap();
+    }
+}
+
+fn read_until(transport: &Rfc2217Transport, expected: usize) -> Vec<u8> {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut received = Vec::new();
+    let mut buf = [0u8; 256];
+
+    while received.len() < expected && Instant::now() < deadline {
+        if let Ok(read_bytes) = transport.read(&mut buf) {
+            received.extend_from_slice(&buf[..read_bytes]);
+        }
+    }
+
+    received
+}
+
+#[test]
+fn negotiates_settings_and_escapes_data() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    thread::spawn(move || stand_in_server(listener.accept().unwrap().0));
+
+    let transport = Rfc2217Transport::open(&address, &SerialConfig::default()).unwrap();
+
+    let payload = [0x00, rfc2217::IAC, 0x41, rfc2217::IAC, rfc2217::IAC, 0x0d];
+    assert_eq!(transport.write(&payload).unwrap(), payload.len());
+    assert_eq!(read_until(&transport, payload.len()), payload);
+
+    assert_eq!(transport.remote_config().unwrap().baudrate, 57600);
+    assert!(transport.modem_status().unwrap().cts);
+}
+
+#[test]
+fn settings_the_server_never_acknowledged_are_not_reported() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    // Takes every command and answers none.
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 256];
+        while stream.read(&mut buf).is_ok_and(|read_bytes| read_bytes > 0) {}
+    });
+
+    let transport = Rfc2217Transport::open(&address, &SerialConfig::default()).unwrap();
+    let error = transport.settings().unwrap_err();
+    assert!(error.to_string().contains("baud rate"), "{error}");
+}
+
+fn wait_until(condition: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(2);
+
+    while !condition() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    condition()
+}
+
+#[test]
+fn bridge_drops_a_client_that_reads_nothing() {
+    let bridge = Bridge::start("127.0.0.1:0", SerialConfig::default(), false).unwrap();
+    let _client = TcpStream::connect(bridge.local_addr()).unwrap();
+    assert!(wait_until(|| bridge.clients().len() == 1));
+
+    // Far more than the socket buffers and the client's queue hold.
+    let started = Instant::now();
+
+    for _ in 0..4096 {
+        bridge.broadcast(&[0x55; 16 << 10]);
+    }
+
+    assert!(started.elapsed() < Duration::from_secs(1), "broadcast took {:?}", started.elapsed());
+    assert!(wait_until(|| bridge.clients().is_empty()));
+}
+
+#[test]
+fn bridge_answers_control_requests_with_the_current_state() {
+    let bridge = Bridge::start("127.0.0.1:0", SerialConfig::default(), true).unwrap();
+    let mut client = TcpStream::connect(bridge.local_addr()).unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+
+    for value in [control::DTR_OFF, control::DTR_REQUEST, control::RTS_REQUEST, control::BREAK_REQUEST] {
+        client.write_all(&rfc2217::com_port_command(command::SET_CONTROL, &[value])).unwrap();
+    }
+
+    let mut telnet = Telnet::new();
+    let mut buf = [0u8; 256];
+    let mut replies = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(2);
+
+    while replies.len() < 4 && Instant::now() < deadline {
+        let Ok(read_bytes) = client.read(&mut buf) else {
+            continue;
+        };
+
+        let mut events = Vec::new();
+        telnet.decode(&buf[..read_bytes], &mut Vec::new(), &mut events);
+
+        for event in events {
+            if let Event::Subnegotiation(rfc2217::COM_PORT_OPTION, payload) = event {
+                if payload[0] == command::SET_CONTROL + command::SERVER_OFFSET {
+                    replies.push(payload[1]);
+                }
+            }
+        }
+    }
+
+    assert_eq!(replies, [control::DTR_OFF, control::DTR_OFF, control::RTS_ON, control::BREAK_OFF]);
+}
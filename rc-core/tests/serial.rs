@@ -0,0 +1,498 @@
+use std::io::Write;
+use std::sync::{atomic::Ordering, Arc};
+use std::time::{Duration, Instant};
+
+use rc_core::serial::{LineWait, Pacing, Parity, Rs485, Serial, SerialConfig, SerialEvent, TransmitStatus};
+use rc_core::transport::{LineErrors, Transport};
+
+fn receive(serial: &Serial, expected: usize) -> Vec<u8> {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut received = Vec::new();
+
+    while received.len() < expected && Instant::now() < deadline {
+        match serial.try_recv() {
+            Some(data) => received.extend(data),
+            None => std::thread::sleep(Duration::from_millis(1)),
+        }
+    }
+
+    received
+}
+
+#[test]
+fn loopback_echoes_binary_data() {
+    let serial = Serial::new();
+    serial.start("loop://", SerialConfig::default()).unwrap();
+
+    let payload = [0x00, 0xff, 0xc3, 0x28, b'\n'];
+    serial.send(&payload).unwrap();
+
+    assert_eq!(receive(&serial, payload.len()), payload);
+    serial.stop().unwrap();
+}
+
+#[test]
+fn loopback_delays_echo() {
+    let serial = Serial::new();
+    serial.start("loop://?delay_ms=100", SerialConfig::default()).unwrap();
+
+    let sent = Instant::now();
+    serial.send(b"ping").unwrap();
+
+    assert_eq!(receive(&serial, 4), b"ping");
+    assert!(sent.elapsed() >= Duration::from_millis(100));
+    serial.stop().unwrap();
+}
+
+#[test]
+fn pacing_waits_for_each_line_to_echo() {
+    let pacing = Pacing {
+        line_wait: LineWait::Echo,
+        ..Default::default()
+    };
+
+    let serial = Serial::new();
+    serial.start("loop://?delay_ms=100", SerialConfig { pacing, ..Default::default() }).unwrap();
+
+    let sent = Instant::now();
+    serial.send(b"one\r\ntwo\r\n").unwrap();
+
+    assert_eq!(receive(&serial, 5), b"one\r\n");
+    assert!(sent.elapsed() >= Duration::from_millis(100));
+    assert_eq!(receive(&serial, 5), b"two\r\n");
+    assert!(sent.elapsed() >= Duration::from_millis(200));
+    serial.stop().unwrap();
+}
+
+#[test]
+fn stop_interrupts_paced_send() {
+    let pacing = Pacing {
+        char_delay: Duration::from_millis(10),
+        ..Default::default()
+    };
+
+    let serial = Serial::new();
+    serial.start("loop://", SerialConfig { pacing, ..Default::default() }).unwrap();
+    serial.send(&[b'x'; 10_000]).unwrap();
+    receive(&serial, 1);
+
+    let stopping = Instant::now();
+    serial.stop().unwrap();
+    assert!(stopping.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn rs485_toggles_rts_and_removes_echo() {
+    let rs485 = Rs485 { enabled: true, remove_echo: true, ..Default::default() };
+
+    let serial = Serial::new();
+    serial.start("loop://", SerialConfig { rs485: rs485.clone(), ..Default::default() }).unwrap();
+
+    // Loopback has no RS-485 mode of its own, and CTS follows RTS.
+    assert!(serial.rs485_software_rts());
+    assert!(!serial.modem_status().unwrap().cts);
+
+    serial.send(b"ping").unwrap();
+    wait_for(&serial, |status| status.written == 4);
+    std::thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(serial.try_recv(), None);
+    assert!(!serial.modem_status().unwrap().cts);
+
+    serial.configure(&SerialConfig { rs485: Rs485 { remove_echo: false, ..rs485 }, ..Default::default() }).unwrap();
+    serial.send(b"pong").unwrap();
+    assert_eq!(receive(&serial, 4), b"pong");
+
+    serial.stop().unwrap();
+}
+
+#[test]
+fn rs485_releases_rts_while_waiting_for_prompt() {
+    let config = SerialConfig {
+        pacing: Pacing { line_wait: LineWait::Prompt(String::from("> ")), ..Default::default() },
+        rs485: Rs485 { enabled: true, ..Default::default() },
+        ..Default::default()
+    };
+
+    let serial = Serial::new();
+    serial.start("loop://", config).unwrap();
+    serial.send(b"one\r\ntwo\r\n").unwrap();
+
+    assert_eq!(receive(&serial, 5), b"one\r\n");
+    std::thread::sleep(Duration::from_millis(100));
+
+    // Still waiting for the prompt after the first line, with the bus free for the reply.
+    assert_eq!(serial.transmit_status().written, 5);
+    assert!(!serial.modem_status().unwrap().cts);
+
+    serial.stop().unwrap();
+}
+
+#[test]
+fn mock_replays_script() {
+    let path = std::env::temp_dir().join(format!("rustcom-mock-{}.toml", std::process::id()));
+    let mut script = std::fs::File::create(&path).unwrap();
+    write!(script, r#"
+greeting = "boot\r\n"
+
+[[response]]
+expect = "AT\r"
+reply = "OK\r\n"
+delay_ms = 20
+"#).unwrap();
+
+    let serial = Serial::new();
+    serial.start(&format!("mock://{}", path.display()), SerialConfig::default()).unwrap();
+    assert_eq!(receive(&serial, 6), b"boot\r\n");
+
+    serial.send(b"A").unwrap();
+    serial.send(b"T\r").unwrap();
+    assert_eq!(receive(&serial, 4), b"OK\r\n");
+
+    serial.stop().unwrap();
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+fn loopback_reports_modem_line_changes() {
+    let serial = Serial::new();
+    serial.start("loop://", SerialConfig { dtr: Some(true), ..Default::default() }).unwrap();
+
+    serial.set_rts(true).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut status = None;
+
+    while Instant::now() < deadline {
+        match serial.try_recv_modem_status() {
+            Some(changed) if changed.cts => {
+                status = Some(changed);
+                break;
+            },
+            _ => std::thread::sleep(Duration::from_millis(5)),
+        }
+    }
+
+    let status = status.expect("CTS never followed RTS");
+    assert!(status.dsr && status.cd && !status.ri);
+    serial.stop().unwrap();
+}
+
+#[test]
+fn tcp_reports_disconnect_and_reconnects() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let device = format!("tcp://{}", listener.local_addr().unwrap());
+
+    let serial = Serial::new();
+    serial.start(&device, SerialConfig::default()).unwrap();
+    drop(listener.accept().unwrap());
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut events = Vec::new();
+
+    while !events.iter().any(|event| matches!(event, SerialEvent::Disconnected(Some(_)))) && Instant::now() < deadline {
+        match serial.try_recv_event() {
+            Some(event) => events.push(event),
+            None => std::thread::sleep(Duration::from_millis(5)),
+        }
+    }
+
+    assert_eq!(events.first(), Some(&SerialEvent::Connected(device.clone())));
+    assert!(matches!(events.last(), Some(SerialEvent::Disconnected(Some(_)))), "{events:?}");
+    serial.stop().ok();
+
+    assert_eq!(serial.reconnect().unwrap(), device);
+    let (mut peer, _) = listener.accept().unwrap();
+    peer.write_all(b"back").unwrap();
+
+    assert_eq!(receive(&serial, 4), b"back");
+    serial.stop().unwrap();
+}
+
+#[test]
+fn stop_returns_while_peer_reads_nothing() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let device = format!("tcp://{}", listener.local_addr().unwrap());
+
+    let serial = Serial::new();
+    serial.start(&device, SerialConfig::default()).unwrap();
+    let _peer = listener.accept().unwrap();
+
+    // Far more than the socket buffers hold.
+    serial.send(&vec![0x55; 64 << 20]).unwrap();
+    wait_for(&serial, |status| status.written > 0);
+    std::thread::sleep(Duration::from_millis(200));
+
+    let started = Instant::now();
+    serial.stop().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1), "stop took {:?}", started.elapsed());
+}
+
+#[test]
+fn restart_drops_what_the_last_session_left_unread() {
+    let serial = Serial::new();
+    serial.start("loop://", SerialConfig::default()).unwrap();
+    serial.send(b"old").unwrap();
+    wait_for(&serial, |status| status.written >= 3);
+    std::thread::sleep(Duration::from_millis(100));
+
+    serial.start("loop://", SerialConfig::default()).unwrap();
+    serial.send(b"new").unwrap();
+
+    assert_eq!(receive(&serial, 3), b"new");
+    assert_eq!(serial.try_recv_event(), Some(SerialEvent::Connected("loop://".to_string())));
+    serial.stop().unwrap();
+}
+
+#[test]
+fn send_fails_when_closed() {
+    let serial = Serial::new();
+    assert!(serial.send(b"lost").is_err());
+
+    serial.start("loop://", SerialConfig::default()).unwrap();
+    serial.stop().unwrap();
+
+    assert!(serial.send(b"lost").is_err());
+    assert_eq!(serial.try_recv_event(), Some(SerialEvent::Connected("loop://".to_string())));
+    assert_eq!(serial.try_recv_event(), Some(SerialEvent::Disconnected(None)));
+}
+
+/// Stands in for a port in tests of what `Serial` does with writes and the
+/// driver's counts. Reads never return data.
+#[derive(Default)]
+struct TestTransport {
+    /// Bytes accepted per write, all of them if zero.
+    chunk_size: usize,
+    flow_control: bool,
+    /// Accepts nothing while set, like a device holding off.
+    held_off: std::sync::atomic::AtomicBool,
+    /// Every write with the parity configured at the time.
+    writes: std::sync::Mutex<Vec<(Option<Parity>, Vec<u8>)>>,
+    configs: std::sync::Mutex<Vec<SerialConfig>>,
+    line_errors: std::sync::Mutex<LineErrors>,
+}
+
+impl TestTransport {
+    /// All bytes written, however many writes they took.
+    fn written(&self) -> Vec<u8> {
+        self.writes.lock().unwrap().iter().flat_map(|(_, bytes)| bytes.clone()).collect()
+    }
+
+    fn parity(&self) -> Option<Parity> {
+        self.configs.lock().unwrap().last().map(|config| config.parity)
+    }
+}
+
+impl Transport for TestTransport {
+    fn open(_name: &str, _config: &SerialConfig) -> std::io::Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        std::thread::sleep(Duration::from_millis(10));
+        Err(std::io::ErrorKind::TimedOut.into())
+    }
+
+    fn write(&self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.held_off.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(10));
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+
+        let accepted = match self.chunk_size {
+            0 => buf.len(),
+            chunk_size => buf.len().min(chunk_size),
+        };
+
+        self.writes.lock().unwrap().push((self.parity(), buf[..accepted].to_vec()));
+        Ok(accepted)
+    }
+
+    fn configure(&self, config: &SerialConfig) -> std::io::Result<()> {
+        self.configs.lock().unwrap().push(config.clone());
+        Ok(())
+    }
+
+    fn settings(&self) -> std::io::Result<SerialConfig> {
+        let flow_control = match self.flow_control {
+            true => serial2::FlowControl::RtsCts,
+            false => serial2::FlowControl::None,
+        };
+
+        Ok(SerialConfig { flow_control, ..SerialConfig::default() })
+    }
+
+    fn line_errors(&self) -> std::io::Result<LineErrors> {
+        Ok(*self.line_errors.lock().unwrap())
+    }
+
+    fn capabilities(&self) -> rc_core::transport::Capabilities {
+        rc_core::transport::Capabilities { configure: true, ..Default::default() }
+    }
+}
+
+/// Takes three bytes per write, so sends need several.
+fn slow_transport(flow_control: bool) -> Arc<TestTransport> {
+    Arc::new(TestTransport { chunk_size: 3, flow_control, ..Default::default() })
+}
+
+fn wait_for(serial: &Serial, condition: impl Fn(TransmitStatus) -> bool) -> TransmitStatus {
+    let deadline = Instant::now() + Duration::from_secs(2);
+
+    loop {
+        let status = serial.transmit_status();
+
+        if condition(status) || Instant::now() >= deadline {
+            return status;
+        }
+
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn partial_writes_and_stalls_are_reported() {
+    let transport = slow_transport(true);
+    transport.held_off.store(true, Ordering::Relaxed);
+
+    let serial = Serial::new();
+    serial.start_with(transport.clone()).unwrap();
+    serial.send(b"0123456789").unwrap();
+
+    let status = wait_for(&serial, |status| status.blocked);
+    assert_eq!(status, TransmitStatus { queued: 10, written: 0, blocked: true });
+
+    transport.held_off.store(false, Ordering::Relaxed);
+
+    let status = wait_for(&serial, |status| status.queued == 0);
+    assert_eq!(status, TransmitStatus { queued: 0, written: 10, blocked: false });
+    assert_eq!(transport.written(), b"0123456789");
+
+    serial.stop().unwrap();
+}
+
+#[test]
+fn stalls_without_flow_control_are_not_blocked() {
+    let transport = slow_transport(false);
+    transport.held_off.store(true, Ordering::Relaxed);
+
+    let serial = Serial::new();
+    serial.start_with(transport.clone()).unwrap();
+    serial.send(b"0123456789").unwrap();
+
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(serial.transmit_status(), TransmitStatus { queued: 10, written: 0, blocked: false });
+
+    serial.stop().unwrap();
+}
+
+#[test]
+fn restart_joins_old_threads_without_cross_talk() {
+    let first = slow_transport(true);
+    first.held_off.store(true, Ordering::Relaxed);
+
+    let serial = Serial::new();
+    serial.start_with(first.clone()).unwrap();
+    serial.send(b"old").unwrap();
+    wait_for(&serial, |status| status.blocked);
+
+    let second = slow_transport(false);
+    serial.start_with(second.clone()).unwrap();
+
+    // Only this test still holds the first transport once its threads are joined.
+    assert_eq!(Arc::strong_count(&first), 1);
+    first.held_off.store(false, Ordering::Relaxed);
+
+    serial.send(b"new").unwrap();
+    wait_for(&serial, |status| status.queued == 0);
+    assert_eq!(second.written(), b"new");
+    assert!(first.written().is_empty());
+
+    drop(serial);
+    assert_eq!(Arc::strong_count(&second), 1);
+}
+
+#[test]
+fn line_errors_count_from_start() {
+    // Counts left over from whoever used the port before.
+    let errors = LineErrors { framing: 7, overrun: 2, ..Default::default() };
+    let transport = Arc::new(TestTransport { line_errors: std::sync::Mutex::new(errors), ..Default::default() });
+
+    let serial = Serial::new();
+    serial.start_with(transport.clone()).unwrap();
+
+    {
+        let mut errors = transport.line_errors.lock().unwrap();
+        errors.framing += 3;
+        errors.parity += 1;
+    }
+
+    let expected = LineErrors { framing: 3, parity: 1, ..Default::default() };
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut reported = None;
+
+    while reported != Some(expected) && Instant::now() < deadline {
+        reported = serial.try_recv_line_errors().or(reported);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    assert_eq!(reported, Some(expected));
+    assert_eq!(expected.total(), 4);
+
+    {
+        let mut errors = transport.line_errors.lock().unwrap();
+        errors.overrun += 5;
+        errors.buffer_overrun += 1;
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut event = None;
+
+    while event.is_none() && Instant::now() < deadline {
+        event = std::iter::from_fn(|| serial.try_recv_event()).find(|event| matches!(event, SerialEvent::Overrun(_)));
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    assert_eq!(event, Some(SerialEvent::Overrun(6)));
+
+    serial.stop().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn busy_port_names_its_holder() {
+    let pty = rc_core::pty::Pty::open().unwrap();
+    let pid = std::process::id();
+
+    let first = Serial::new();
+    first.start(pty.path(), SerialConfig::default()).unwrap();
+    assert_eq!(rc_core::port_lock::holder(pty.path()).map(|holder| holder.pid), Some(pid));
+    assert_eq!(rc_core::port_lock::holders(&[pty.path()])[0].0, pty.path());
+
+    let second = Serial::new();
+    let error = second.start(pty.path(), SerialConfig::default()).unwrap_err();
+    assert!(error.to_string().contains(&format!("pid {pid}")), "{error}");
+
+    first.stop().unwrap();
+    second.start(pty.path(), SerialConfig::default()).unwrap();
+    second.stop().unwrap();
+}
+
+#[test]
+fn address_byte_goes_out_with_mark_parity() {
+    let transport = Arc::new(TestTransport::default());
+
+    let serial = Serial::new();
+    serial.start_with(transport.clone()).unwrap();
+    serial.send_addressed(0x12, b"data").unwrap();
+
+    let status = wait_for(&serial, |status| status.queued == 0);
+    assert_eq!(status.written, 5);
+    serial.stop().unwrap();
+
+    assert_eq!(*transport.writes.lock().unwrap(), [
+        (Some(Parity::Mark), vec![0x12]),
+        (Some(Parity::Space), b"data".to_vec()),
+    ]);
+    assert_eq!(transport.parity(), Some(Parity::None));
+}
@@ -4,13 +4,8 @@ use egui_dock::{Tree, DockArea};
 use flume::{unbounded, Sender, Receiver};
 use log::info;
 use logger::{Entry, Logger, LOGGER};
-use parking_lot::RwLock;
-use arboard::Clipboard;
 
-use std::io::Write;
-use std::sync::Arc;
-use std::fs::OpenOptions;
-use std::ops::DerefMut;
+use std::time::Duration;
 
 mod tabs;
 mod widgets;
@@ -18,192 +13,168 @@ mod menu_bar;
 mod status_bar;
 mod modals;
 mod logger;
+mod session;
 
 use tabs::{Tab, default_ui};
-use widgets::line_end_picker::LineEnd;
-use widgets::file_protocol_picker::Protocol;
+use session::{Session, SessionId, SessionMessage};
 
-use rc_core::serial::{SerialConfig, Serial};
+use rc_core::hotplug::{HotplugEvent, PortWatcher};
+use rc_core::port_lock::Holder;
+use rc_core::serial::{PortInfo, SerialConfig, Serial};
 
 #[derive(Clone)]
 pub enum Message {
-    Connect,
-    Disconnect,
     ShowAbout,
     CloseAbout,
-    Copy,
-    Paste,
-    ClearTerminalText,
     ClearLogText,
-    Cut,
-    SerialDataReceived(String),
-    DataForTransmit(String),
     CloseApplication,
     SetDefaultUi,
     RefreshSerialDevices,
-    StartRecording,
-    StopRecording,
+    Hotplug(HotplugEvent),
+    NewSession,
+    CloseSession(SessionId),
+    Session(SessionId, SessionMessage),
     Log(Entry),
 }
 
 pub struct App {
     channel: (Sender<Message>, Receiver<Message>),
-    tree: Arc<RwLock<Tree<Box<dyn Tab>>>>,
-    pub serial_config: SerialConfig,
-    pub serial: Serial,
-    pub terminal_text: String,
+    tree: Tree<Box<dyn Tab>>,
+    sessions: Vec<Session>,
+    /// Session of the Terminal tab focused last, which the Settings tab,
+    /// the status bar and the menus act on.
+    active_session: SessionId,
+    next_session: SessionId,
     pub log_text: String,
-    pub transmit_text: String,
 
-    pub device_connected: bool,
-    pub current_serial_device: String,
-    pub serial_devices: Vec<String>,
-
-    pub line_end: LineEnd,
-    pub timestamp: bool,
-    pub lock_scrolling: bool,
+    pub serial_devices: Vec<PortInfo>,
+    /// Ports some process has open, shown greyed out in the device list.
+    pub port_holders: Vec<(String, Holder)>,
+    port_watcher: PortWatcher,
 
     show_about: bool,
-
-    rx_cnt: u32,
-    tx_cnt: u32,
-
-    recording_started: bool,
-    log_file_name: String,
-
-    pub file_protocol: Protocol,
-
 }
 
 impl App {
     fn new(_cc: &CreationContext, device: String, config: SerialConfig) -> Self {
-        let mut app = Self {
-            channel: unbounded(),
-            tree: Arc::new(RwLock::new(default_ui())),
-            serial: Serial::new(),
-            serial_config: config,
-            terminal_text: String::new(),
-            transmit_text: String::new(),
-
-            device_connected: false,
-            current_serial_device: String::new(),
-            serial_devices: Serial::available_ports().unwrap_or_default(),
-
-            line_end: LineEnd::default(),
-            timestamp: false,
-            lock_scrolling: true,
+        let channel = unbounded();
+        let serial_devices = Serial::available_ports().unwrap_or_default();
+        let session = Session::new(0, channel.0.clone(), device, config, &serial_devices);
+
+        let app = Self {
+            channel,
+            tree: default_ui(&[session.id]),
+            sessions: vec![session],
+            active_session: 0,
+            next_session: 1,
+            log_text: String::new(),
 
-            show_about: false,
+            serial_devices,
+            port_holders: Vec::new(),
+            port_watcher: PortWatcher::start(),
 
-            rx_cnt: 0,
-            tx_cnt: 0,
+            show_about: false,
+        };
 
-            recording_started: false,
-            log_file_name: String::new(),
+        Logger::global().set_sender(app.channel.0.clone());
 
-            file_protocol: Protocol::default(),
+        app
+    }
 
-            log_text: String::new(),
-        };
+    pub fn session(&self, id: SessionId) -> Option<&Session> {
+        self.sessions.iter().find(|session| session.id == id)
+    }
 
-        Logger::global().set_sender(app.channel.0.clone());
+    pub fn session_mut(&mut self, id: SessionId) -> Option<&mut Session> {
+        self.sessions.iter_mut().find(|session| session.id == id)
+    }
 
-        app.current_serial_device = if !device.is_empty() {
-            device
-        } else if !app.serial_devices.is_empty(){
-            app.serial_devices[0].clone()
-        } else {
-            String::new()
-        };
+    pub fn active_session(&self) -> &Session {
+        self.session(self.active_session).unwrap_or(&self.sessions[0])
+    }
 
-        app
+    pub fn active_session_mut(&mut self) -> &mut Session {
+        let index = self.sessions.iter().position(|session| session.id == self.active_session).unwrap_or(0);
+        &mut self.sessions[index]
     }
 
     pub fn do_update(&self, message: Message) {
         self.channel.0.send(message).unwrap();
     }
 
+    /// Sends `message` to the session the menus and shortcuts act on.
+    pub fn do_session_update(&self, message: SessionMessage) {
+        self.do_update(Message::Session(self.active_session, message));
+    }
+
     fn handle_update(&mut self, _ctx: &Context, frame: &mut Frame) {
-        if let Ok(message) = self.channel.1.try_recv() {
+        // All of them, since every session adds its own each frame.
+        while let Ok(message) = self.channel.1.try_recv() {
             match message {
-                Message::Connect => {
-                    if self.serial.start(&self.current_serial_device, self.serial_config.clone()).is_ok() {
-                        info!("{} connected.", self.current_serial_device);
-                        self.device_connected = true;
-                    } else {
-                        info!("Couldn't connect to {}", self.current_serial_device);
-                    }
-                },
-                Message::Disconnect => {
-                    if self.serial.stop().is_ok() {
-                        info!("{} disconnected.", self.current_serial_device);
-                        self.device_connected = false;
-                    } else {
-                        info!("Couldn't disconnect from {}", self.current_serial_device);
-                    }
-                },
-                Message::DataForTransmit(text) => {
-                    if self.device_connected {
-                        self.tx_cnt += text.len() as u32;
-                        self.serial.send(&text);
-                    }
-                },
-                Message::SerialDataReceived(text) => {
-                    if self.timestamp {
-                        self.terminal_text.push_str(&chrono::Local::now().format(" %H:%M:%S> ").to_string());
-                    }
-
-                    self.rx_cnt += text.len() as u32;
-                    self.terminal_text.push_str(&text);
-
-                    if self.recording_started {
-                        let mut f = OpenOptions::new()
-                            .append(true)
-                            .create(true)
-                            .truncate(false)
-                            .open(self.log_file_name.clone())
-                            .unwrap();
-
-                        f.write_all(text.as_bytes()).unwrap();
-                    }
-                },
                 Message::ShowAbout => self.show_about = true,
                 Message::CloseAbout => self.show_about = false,
-                Message::Copy => Clipboard::new().unwrap().set_text(self.terminal_text.clone()).unwrap(),
-                Message::Cut => {
-                    Clipboard::new().unwrap().set_text(self.terminal_text.clone()).unwrap();
-                    self.terminal_text.clear();
-                },
-                Message::Paste => {
-                    if let Ok(text) = Clipboard::new().unwrap().get_text() {
-                        self.transmit_text.push_str(&text);
-                    }
-                },
-                Message::ClearTerminalText => self.terminal_text.clear(),
                 Message::ClearLogText => self.log_text.clear(),
                 Message::CloseApplication => frame.close(),
-                Message::SetDefaultUi => *self.tree.write() = default_ui(),
+                Message::SetDefaultUi => {
+                    let sessions: Vec<SessionId> = self.sessions.iter().map(|session| session.id).collect();
+                    self.tree = default_ui(&sessions);
+                },
                 Message::RefreshSerialDevices => {
                     if let Ok(serial_devices) = Serial::available_ports() {
                         self.serial_devices = serial_devices;
 
-                        if !self.serial_devices.is_empty() {
-                            self.current_serial_device = self.serial_devices[0].clone();
+                        for session in &mut self.sessions {
+                            session.keep_serial_device_selected(&self.serial_devices);
                         }
                     }
 
                 },
-                Message::StartRecording => {
-                    if let Some(path) = rfd::FileDialog::new()
-                        .set_directory(dirs::home_dir().unwrap())
-                        .pick_file()
-                    {
-                        self.log_file_name = path.to_string_lossy().to_string();
-                        self.recording_started = true;
+                Message::Hotplug(event) => {
+                    match event {
+                        HotplugEvent::Added(port) => {
+                            match port.description() {
+                                description if description.is_empty() => info!("{} arrived.", port.name),
+                                description => info!("{} ({description}) arrived.", port.name),
+                            }
+
+                            if !self.serial_devices.iter().any(|other| other.name == port.name) {
+                                self.serial_devices.push(port);
+                            }
+                        },
+                        HotplugEvent::Removed(port) => {
+                            info!("{} removed.", port.name);
+                            self.serial_devices.retain(|other| other.name != port.name);
+                        },
+                        HotplugEvent::Holders(holders) => self.port_holders = holders,
+                    }
+
+                    for session in &mut self.sessions {
+                        session.keep_serial_device_selected(&self.serial_devices);
                     }
                 },
-                Message::StopRecording => {
-                    self.recording_started = false;
+                Message::NewSession => {
+                    let id = self.next_session;
+                    self.next_session += 1;
+
+                    let config = self.active_session().serial_config.clone();
+                    let session = Session::new(id, self.channel.0.clone(), String::new(), config, &self.serial_devices);
+
+                    self.sessions.push(session);
+                    self.tree.push_to_first_leaf(tabs::terminal_tab(id));
+                    self.active_session = id;
+                },
+                Message::CloseSession(id) => {
+                    // Dropping the session closes its port, bridge and PTY.
+                    self.sessions.retain(|session| session.id != id);
+
+                    if self.active_session == id {
+                        self.active_session = self.sessions[0].id;
+                    }
+                },
+                Message::Session(id, message) => {
+                    if let Some(session) = self.session_mut(id) {
+                        session.handle_update(message);
+                    }
                 },
                 Message::Log(entry) => {
                     entry.format(&mut self.log_text);
@@ -213,36 +184,50 @@ impl App {
     }
 
     fn handle_serial(&self) {
-        if let Some(text) = self.serial.try_recv() {
-            self.do_update(Message::SerialDataReceived(text));
+        for session in &self.sessions {
+            session.handle_serial();
+        }
+
+        if let Some(event) = self.port_watcher.try_recv() {
+            self.do_update(Message::Hotplug(event));
+        }
+    }
+
+    fn handle_reconnect(&mut self) {
+        for session in &mut self.sessions {
+            session.handle_reconnect();
         }
     }
 
     fn handle_repaint(&self, ctx: &Context) {
-        if self.device_connected {
+        if self.sessions.iter().any(Session::is_busy) {
             ctx.request_repaint();
+        } else {
+            // Picks up hotplug events while nobody touches the window.
+            ctx.request_repaint_after(Duration::from_millis(500));
         }
     }
 
     fn handle_keypress(&self, ctx: &Context) {
         let shortcuts = [
-            (Message::Copy, KeyboardShortcut::new(Modifiers::CTRL, Key::C)),
-            (Message::Cut, KeyboardShortcut::new(Modifiers::CTRL, Key::X)),
-            (Message::Paste, KeyboardShortcut::new(Modifiers::CTRL, Key::V)),
-            (Message::ClearTerminalText, KeyboardShortcut::new(Modifiers::CTRL, Key::L)),
+            (SessionMessage::Copy, KeyboardShortcut::new(Modifiers::CTRL, Key::C)),
+            (SessionMessage::Cut, KeyboardShortcut::new(Modifiers::CTRL, Key::X)),
+            (SessionMessage::Paste, KeyboardShortcut::new(Modifiers::CTRL, Key::V)),
+            (SessionMessage::ClearTerminalText, KeyboardShortcut::new(Modifiers::CTRL, Key::L)),
         ];
 
         for (message, shortcut) in &shortcuts {
             if ctx.input_mut(|i| i.consume_shortcut(shortcut)) {
-                self.do_update(message.clone());
+                self.do_session_update(message.clone());
                 break;
             }
         }
 
         if ctx.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Enter)) {
-            let mut s = self.transmit_text.clone();
-            s.push_str(self.line_end.into());
-            self.do_update(Message::DataForTransmit(s));
+            let session = self.active_session();
+            let mut s = session.transmit_text.clone();
+            s.push_str(session.line_end.into());
+            self.do_session_update(SessionMessage::DataForTransmit(s));
         }
     }
 }
@@ -254,15 +239,25 @@ impl eframe::App for App {
         self.render_status_bar(ctx, frame);
 
         self.handle_serial();
+        self.handle_reconnect();
 
         self.handle_repaint(ctx);
         self.handle_keypress(ctx);
 
         self.render_about(ctx);
 
+        // Taken out while shown, since the tabs need `self` as well.
+        let mut tree = std::mem::take(&mut self.tree);
+
+        if let Some(session) = tree.find_active_focused().and_then(|(_, tab)| tab.session()) {
+            self.active_session = session;
+        }
+
         CentralPanel::default().show(ctx, |ui| {
-            DockArea::new(self.tree.clone().write().deref_mut()).show_inside(ui, self);
+            DockArea::new(&mut tree).show_inside(ui, self);
         });
+
+        self.tree = tree;
     }
 
     fn save(&mut self, _storage: &mut dyn eframe::Storage) {}
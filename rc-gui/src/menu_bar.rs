@@ -1,4 +1,5 @@
 use super::{App, Message};
+use crate::session::SessionMessage;
 use eframe::egui::{Context, TopBottomPanel, Ui};
 
 impl App {
@@ -15,6 +16,7 @@ impl App {
             ui.horizontal(|ui| {
                 ui.menu_button("File", |ui| self.file_menu(ui));
                 ui.menu_button("Edit", |ui| self.edit_menu(ui));
+                ui.menu_button("Port", |ui| self.port_menu(ui));
                 ui.menu_button("Window", |ui| self.window_menu(ui));
                 ui.menu_button("Help", |ui| self.help_menu(ui));
             });
@@ -26,11 +28,17 @@ impl App {
     }
 
     pub fn edit_menu(&self, ui: &mut Ui) {
-        self.create_menu_item(ui, "Cut", Message::Cut, Some("  Ctrl+X"));
-        self.create_menu_item(ui, "Copy", Message::Copy, Some("Ctrl+C"));
-        self.create_menu_item(ui, "Paste", Message::Paste, Some("Ctrl+V"));
+        self.create_menu_item(ui, "Cut", Message::Session(self.active_session, SessionMessage::Cut), Some("  Ctrl+X"));
+        self.create_menu_item(ui, "Copy", Message::Session(self.active_session, SessionMessage::Copy), Some("Ctrl+C"));
+        self.create_menu_item(ui, "Paste", Message::Session(self.active_session, SessionMessage::Paste), Some("Ctrl+V"));
         ui.separator();
-        self.create_menu_item(ui, "Clear", Message::ClearTerminalText, Some("Ctrl+L"));
+        self.create_menu_item(ui, "Clear", Message::Session(self.active_session, SessionMessage::ClearTerminalText), Some("Ctrl+L"));
+    }
+
+    pub fn port_menu(&self, ui: &mut Ui) {
+        self.create_menu_item(ui, "New terminal", Message::NewSession, None);
+        ui.separator();
+        self.create_menu_item(ui, "Send break", Message::Session(self.active_session, SessionMessage::SendBreak), None);
     }
 
     pub fn window_menu(&self, ui: &mut Ui) {
@@ -0,0 +1,598 @@
+use arboard::Clipboard;
+use flume::{unbounded, Receiver, Sender};
+use log::{debug, info, warn};
+
+use std::io::Write;
+use std::thread;
+use std::fs::OpenOptions;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::Message;
+use crate::widgets::line_end_picker::LineEnd;
+use crate::widgets::file_protocol_picker::Protocol;
+
+use rc_core::autobaud::{self, AutobaudOptions, Candidate};
+use rc_core::bridge::{Bridge, BridgeEvent};
+use rc_core::pty::Pty;
+use rc_core::serial::{PortInfo, SerialConfig, SerialEvent, Serial};
+use rc_core::text::TextDecoder;
+use rc_core::transport::{ConnectionType, LineErrors, ModemStatus, ERROR_SUBSTITUTE};
+
+pub type SessionId = usize;
+
+/// Messages for one session, sent as `Message::Session`.
+#[derive(Clone)]
+pub enum SessionMessage {
+    Connect,
+    Disconnect,
+    ApplySerialConfig,
+    Copy,
+    Paste,
+    ClearTerminalText,
+    Cut,
+    SerialDataReceived(Vec<u8>),
+    DataForTransmit(String),
+    StartRecording,
+    StopRecording,
+    StartBridge,
+    StopBridge,
+    Bridge(BridgeEvent),
+    OpenPty,
+    ClosePty,
+    PtyDataReceived(Vec<u8>),
+    PtyFailed(String),
+    SetDtr(bool),
+    SetRts(bool),
+    SendBreak,
+    ModemStatusChanged(ModemStatus),
+    LineErrorsChanged(LineErrors),
+    Serial(SerialEvent),
+    StartAutobaud,
+    AutobaudFinished(Result<Vec<Candidate>, String>),
+    /// A reconnect attempt is over: the device it reopened, or why not.
+    ReconnectFinished(Result<String, String>),
+}
+
+/// One port with its settings, terminal text, recording and statistics,
+/// shown by its own Terminal tab.
+pub struct Session {
+    pub id: SessionId,
+    channel: Sender<Message>,
+
+    pub serial_config: SerialConfig,
+    /// Shared with the thread of a reconnect attempt.
+    pub serial: Arc<Serial>,
+    text_decoder: TextDecoder,
+    pub terminal_text: String,
+    pub transmit_text: String,
+
+    pub device_connected: bool,
+    pub connection_type: ConnectionType,
+    pub current_serial_device: String,
+    pub device_address: String,
+
+    pub dtr: bool,
+    pub rts: bool,
+    pub modem_status: ModemStatus,
+    pub break_duration_ms: u64,
+
+    pub auto_reconnect: bool,
+    reconnecting: bool,
+    last_reconnect_attempt: Instant,
+    /// Grows with every failed attempt, up to `MAX_RECONNECT_INTERVAL`.
+    reconnect_interval: Duration,
+    /// The attempt under way, which can take seconds for network ports.
+    reconnect_attempt: Option<Receiver<Result<String, String>>>,
+
+    pub line_end: LineEnd,
+    /// Typed lines go to the multidrop device at `address`.
+    pub address_mode: bool,
+    pub address: u8,
+    pub timestamp: bool,
+    pub lock_scrolling: bool,
+
+    pub rx_cnt: u32,
+    /// `None` while the driver doesn't count receive errors.
+    pub line_errors: Option<LineErrors>,
+
+    pub recording_started: bool,
+    pub log_file_name: String,
+
+    pub bridge: Option<Bridge>,
+    pub bridge_address: String,
+    pub bridge_rfc2217: bool,
+
+    pub pty: Option<Pty>,
+
+    autobaud: Option<Receiver<Result<Vec<Candidate>, String>>>,
+    pub autobaud_candidates: Vec<Candidate>,
+
+    pub file_protocol: Protocol,
+}
+
+impl Session {
+    /// Where the wait between reconnect attempts stops doubling.
+    const MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+    pub fn new(id: SessionId, channel: Sender<Message>, device: String, config: SerialConfig, serial_devices: &[PortInfo]) -> Self {
+        let mut session = Self {
+            id,
+            channel,
+
+            serial_config: config,
+            serial: Arc::new(Serial::new()),
+            text_decoder: TextDecoder::new(),
+            terminal_text: String::new(),
+            transmit_text: String::new(),
+
+            device_connected: false,
+            connection_type: ConnectionType::default(),
+            current_serial_device: String::new(),
+            device_address: String::new(),
+
+            dtr: true,
+            rts: true,
+            modem_status: ModemStatus::default(),
+            break_duration_ms: 250,
+
+            auto_reconnect: true,
+            reconnecting: false,
+            last_reconnect_attempt: Instant::now(),
+            reconnect_interval: Serial::RECONNECT_INTERVAL,
+            reconnect_attempt: None,
+
+            line_end: LineEnd::default(),
+            address_mode: false,
+            address: 0,
+            timestamp: false,
+            lock_scrolling: true,
+
+            rx_cnt: 0,
+            line_errors: None,
+
+            recording_started: false,
+            log_file_name: String::new(),
+
+            bridge: None,
+            bridge_address: String::from("0.0.0.0:2217"),
+            bridge_rfc2217: false,
+
+            pty: None,
+
+            autobaud: None,
+            autobaud_candidates: Vec::new(),
+
+            file_protocol: Protocol::default(),
+        };
+
+        let (connection_type, address) = ConnectionType::parse(&device);
+        if connection_type != ConnectionType::Serial {
+            session.connection_type = connection_type;
+            session.device_address = address.to_string();
+        }
+
+        if !device.is_empty() && session.connection_type == ConnectionType::Serial {
+            session.current_serial_device = device;
+        } else {
+            session.keep_serial_device_selected(serial_devices);
+        }
+
+        session
+    }
+
+    pub fn device(&self) -> String {
+        match self.connection_type {
+            ConnectionType::Serial => self.current_serial_device.clone(),
+            connection_type => connection_type.device(&self.device_address),
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match self.device() {
+            device if device.is_empty() => format!("Terminal {}", self.id + 1),
+            device => device,
+        }
+    }
+
+    /// The port went away and is opened again as soon as it comes back.
+    /// Also true while an attempt given up on is still finishing.
+    pub fn is_reconnecting(&self) -> bool {
+        self.reconnecting || self.reconnect_attempt.is_some()
+    }
+
+    pub fn is_detecting_baudrate(&self) -> bool {
+        self.autobaud.is_some()
+    }
+
+    /// Something is going on that needs the window to keep repainting.
+    pub fn is_busy(&self) -> bool {
+        self.device_connected || self.is_reconnecting() || self.is_detecting_baudrate()
+    }
+
+    /// Selects the first port only when nothing is selected yet, so a choice
+    /// survives refreshes and is still there when its port comes back.
+    pub fn keep_serial_device_selected(&mut self, serial_devices: &[PortInfo]) {
+        if self.current_serial_device.is_empty() {
+            if let Some(port) = serial_devices.first() {
+                self.current_serial_device = port.name.clone();
+            }
+        }
+    }
+
+    fn check_effective_config(&self) {
+        if let Ok(applied) = self.serial.effective_config() {
+            for mismatch in self.serial_config.mismatches(&applied) {
+                warn!("{}: {mismatch}", self.device());
+            }
+        }
+    }
+
+    pub fn do_update(&self, message: SessionMessage) {
+        self.channel.send(Message::Session(self.id, message)).unwrap();
+    }
+
+    pub fn handle_update(&mut self, message: SessionMessage) {
+        match message {
+            SessionMessage::Connect => {
+                match self.serial.start(&self.device(), self.serial_config.clone()) {
+                    Ok(()) => {
+                        self.text_decoder.reset();
+                        self.dtr = self.serial_config.dtr.unwrap_or(true);
+                        self.rts = self.serial_config.rts.unwrap_or(true);
+                        self.modem_status = ModemStatus::default();
+                        self.line_errors = None;
+                        self.reconnecting = false;
+                        self.check_effective_config();
+                    },
+                    Err(e) => warn!("Couldn't connect to {}: {e}", self.device()),
+                }
+            },
+            SessionMessage::Disconnect => {
+                if self.bridge.is_some() {
+                    self.do_update(SessionMessage::StopBridge);
+                }
+
+                if self.pty.is_some() {
+                    self.do_update(SessionMessage::ClosePty);
+                }
+
+                if self.reconnecting {
+                    info!("Stopped waiting for {}.", self.device());
+                    self.reconnecting = false;
+                } else if let Err(e) = self.serial.stop() {
+                    warn!("Couldn't disconnect from {}: {e}", self.device());
+                }
+            },
+            SessionMessage::ApplySerialConfig => {
+                if let Some(bridge) = &self.bridge {
+                    bridge.set_config(self.serial_config.clone());
+                }
+
+                match self.serial.configure(&self.serial_config) {
+                    Ok(()) => {
+                        info!("{} reconfigured.", self.device());
+                        self.check_effective_config();
+                    },
+                    Err(e) => warn!("Couldn't reconfigure {}: {e}", self.device()),
+                }
+            },
+            SessionMessage::DataForTransmit(text) if self.address_mode => self.transmit_addressed(text.as_bytes()),
+            SessionMessage::DataForTransmit(text) => self.transmit(text.as_bytes()),
+            SessionMessage::SerialDataReceived(data) => {
+                if self.timestamp {
+                    self.terminal_text.push_str(&chrono::Local::now().format(" %H:%M:%S> ").to_string());
+                }
+
+                self.rx_cnt += data.len() as u32;
+                let text = self.text_decoder.decode(&data);
+
+                if self.serial_config.mark_errors {
+                    self.terminal_text.push_str(&text.replace(char::from(ERROR_SUBSTITUTE), "\u{FFFD}"));
+                } else {
+                    self.terminal_text.push_str(&text);
+                }
+
+                if self.recording_started {
+                    let mut f = OpenOptions::new()
+                        .append(true)
+                        .create(true)
+                        .truncate(false)
+                        .open(self.log_file_name.clone())
+                        .unwrap();
+
+                    f.write_all(&data).unwrap();
+                }
+            },
+            SessionMessage::Copy => Clipboard::new().unwrap().set_text(self.terminal_text.clone()).unwrap(),
+            SessionMessage::Cut => {
+                Clipboard::new().unwrap().set_text(self.terminal_text.clone()).unwrap();
+                self.terminal_text.clear();
+            },
+            SessionMessage::Paste => {
+                if let Ok(text) = Clipboard::new().unwrap().get_text() {
+                    self.transmit_text.push_str(&text);
+                }
+            },
+            SessionMessage::ClearTerminalText => self.terminal_text.clear(),
+            SessionMessage::StartRecording => {
+                if let Some(path) = rfd::FileDialog::new()
+                    .set_directory(dirs::home_dir().unwrap())
+                    .pick_file()
+                {
+                    self.log_file_name = path.to_string_lossy().to_string();
+                    self.recording_started = true;
+                }
+            },
+            SessionMessage::StopRecording => {
+                self.recording_started = false;
+            },
+            SessionMessage::StartBridge => {
+                match Bridge::start(&self.bridge_address, self.serial_config.clone(), self.bridge_rfc2217) {
+                    Ok(bridge) => {
+                        info!("Bridging {} on {}.", self.device(), bridge.local_addr());
+                        self.bridge = Some(bridge);
+                    },
+                    Err(e) => info!("Couldn't start bridge on {}: {e}", self.bridge_address),
+                }
+            },
+            SessionMessage::StopBridge => {
+                if let Some(bridge) = self.bridge.take() {
+                    bridge.stop();
+                    info!("Bridge on {} stopped.", bridge.local_addr());
+                }
+            },
+            SessionMessage::Bridge(event) => match event {
+                BridgeEvent::ClientConnected(address) => info!("Bridge client {address} connected."),
+                BridgeEvent::ClientDisconnected(address) => info!("Bridge client {address} disconnected."),
+                BridgeEvent::Error(error) => warn!("Bridge: {error}"),
+                BridgeEvent::Data(data) => self.transmit(&data),
+                BridgeEvent::Configure(config) => {
+                    self.serial_config = config;
+                    self.do_update(SessionMessage::ApplySerialConfig);
+                },
+                BridgeEvent::SetDtr(state) => self.do_update(SessionMessage::SetDtr(state)),
+                BridgeEvent::SetRts(state) => self.do_update(SessionMessage::SetRts(state)),
+                BridgeEvent::SetBreak(state) => {
+                    if self.serial.set_break(state).is_err() {
+                        info!("Couldn't set BREAK on {}", self.device());
+                    }
+                },
+            },
+            SessionMessage::OpenPty => {
+                match Pty::open() {
+                    Ok(pty) => {
+                        info!("{} available at {}.", self.device(), pty.path());
+                        self.pty = Some(pty);
+                    },
+                    Err(e) => info!("Couldn't create pseudo-terminal: {e}"),
+                }
+            },
+            SessionMessage::ClosePty => {
+                if let Some(pty) = self.pty.take() {
+                    info!("{} closed.", pty.path());
+                }
+            },
+            SessionMessage::PtyDataReceived(data) => self.transmit(&data),
+            SessionMessage::PtyFailed(error) => {
+                if let Some(pty) = self.pty.take() {
+                    warn!("{} failed and was closed: {error}", pty.path());
+                }
+            },
+            SessionMessage::SetDtr(state) => {
+                if self.serial.set_dtr(state).is_ok() {
+                    self.dtr = state;
+                } else {
+                    info!("Couldn't set DTR on {}", self.device());
+                }
+            },
+            SessionMessage::SetRts(state) => {
+                if self.serial.set_rts(state).is_ok() {
+                    self.rts = state;
+                } else {
+                    info!("Couldn't set RTS on {}", self.device());
+                }
+            },
+            SessionMessage::SendBreak => {
+                if self.device_connected && self.serial.send_break(Duration::from_millis(self.break_duration_ms)).is_ok() {
+                    info!("BREAK sent to {} for {} ms.", self.device(), self.break_duration_ms);
+                } else {
+                    info!("Couldn't send BREAK to {}", self.device());
+                }
+            },
+            SessionMessage::ModemStatusChanged(status) => {
+                debug!("CTS: {} DSR: {} RI: {} CD: {}", status.cts, status.dsr, status.ri, status.cd);
+
+                if let Some(bridge) = &self.bridge {
+                    bridge.notify_modem_status(status);
+                }
+
+                self.modem_status = status;
+            },
+            SessionMessage::LineErrorsChanged(errors) => {
+                debug!("Framing: {} Parity: {} Overrun: {} Buffer overrun: {} Break: {}",
+                    errors.framing, errors.parity, errors.overrun, errors.buffer_overrun, errors.breaks);
+
+                self.line_errors = Some(errors);
+            },
+            SessionMessage::Serial(SerialEvent::Connected(device)) => {
+                info!("{device} connected.");
+                self.device_connected = true;
+            },
+            SessionMessage::Serial(SerialEvent::Disconnected(None)) => {
+                if self.device_connected {
+                    info!("{} disconnected.", self.device());
+                }
+
+                self.device_connected = false;
+            },
+            SessionMessage::Serial(SerialEvent::ReadError(error)) => warn!("{}: read failed: {error}", self.device()),
+            SessionMessage::Serial(SerialEvent::WriteError(error)) => warn!("{}: write failed: {error}", self.device()),
+            SessionMessage::Serial(SerialEvent::BreakError(error)) => warn!("{}: BREAK failed: {error}", self.device()),
+            SessionMessage::Serial(SerialEvent::Overrun(lost)) => warn!("{}: {lost} bytes lost to receive overruns", self.device()),
+            SessionMessage::Serial(SerialEvent::Disconnected(Some(error))) => {
+                warn!("{} disconnected: {error}", self.device());
+                self.serial.stop().ok();
+                self.device_connected = false;
+
+                if self.auto_reconnect {
+                    info!("Waiting for {} to come back...", self.device());
+                    self.reconnecting = true;
+                    self.last_reconnect_attempt = Instant::now();
+                    self.reconnect_interval = Serial::RECONNECT_INTERVAL;
+                } else {
+                    if self.bridge.is_some() {
+                        self.do_update(SessionMessage::StopBridge);
+                    }
+
+                    if self.pty.is_some() {
+                        self.do_update(SessionMessage::ClosePty);
+                    }
+                }
+            },
+            SessionMessage::StartAutobaud => {
+                let (sender, receiver) = unbounded();
+                let device = self.device();
+                let config = self.serial_config.clone();
+
+                info!("Detecting baud rate of {device}...");
+
+                thread::spawn(move || {
+                    let result = autobaud::scan(&device, &config, &AutobaudOptions::default());
+                    sender.send(result.map_err(|e| e.to_string())).ok();
+                });
+
+                self.autobaud_candidates.clear();
+                self.autobaud = Some(receiver);
+            },
+            SessionMessage::AutobaudFinished(result) => {
+                self.autobaud = None;
+
+                match result {
+                    Ok(candidates) => {
+                        match candidates.first() {
+                            Some(best) if best.score > 0.0 => {
+                                info!("{} looks like {} {}.", self.device(), best.config.baudrate, best.config.framing());
+                            },
+                            _ => info!("No data received from {}.", self.device()),
+                        }
+
+                        self.autobaud_candidates = candidates;
+                    },
+                    Err(e) => warn!("Couldn't detect baud rate of {}: {e}", self.device()),
+                }
+            },
+            SessionMessage::ReconnectFinished(result) => {
+                self.reconnect_attempt = None;
+                self.last_reconnect_attempt = Instant::now();
+
+                match result {
+                    // Disconnect was pressed while the attempt was under way.
+                    Ok(_) if !self.reconnecting => {
+                        self.serial.stop().ok();
+                    },
+                    Ok(device) => {
+                        if self.connection_type == ConnectionType::Serial && device != self.current_serial_device {
+                            info!("{} is now {device}.", self.current_serial_device);
+                            self.current_serial_device = device;
+                        }
+
+                        self.text_decoder.reset();
+                        self.reconnecting = false;
+
+                        if let Some(bridge) = &self.bridge {
+                            bridge.set_config(self.serial_config.clone());
+                        }
+                    },
+                    Err(e) => {
+                        debug!("Couldn't reconnect to {}: {e}", self.device());
+                        self.reconnect_interval = (self.reconnect_interval * 2).min(Self::MAX_RECONNECT_INTERVAL);
+                    },
+                }
+            },
+        }
+    }
+
+    pub fn handle_serial(&self) {
+        if let Some(data) = self.serial.try_recv() {
+            if let Some(bridge) = &self.bridge {
+                bridge.broadcast(&data);
+            }
+
+            if let Some(pty) = &self.pty {
+                pty.write(&data);
+            }
+
+            self.do_update(SessionMessage::SerialDataReceived(data));
+        }
+
+        if let Some(status) = self.serial.try_recv_modem_status() {
+            self.do_update(SessionMessage::ModemStatusChanged(status));
+        }
+
+        if let Some(errors) = self.serial.try_recv_line_errors() {
+            self.do_update(SessionMessage::LineErrorsChanged(errors));
+        }
+
+        if let Some(event) = self.serial.try_recv_event() {
+            self.do_update(SessionMessage::Serial(event));
+        }
+
+        if let Some(data) = self.pty.as_ref().and_then(|pty| pty.try_recv()) {
+            self.do_update(SessionMessage::PtyDataReceived(data));
+        }
+
+        if let Some(error) = self.pty.as_ref().and_then(|pty| pty.try_recv_error()) {
+            self.do_update(SessionMessage::PtyFailed(error.to_string()));
+        }
+
+        if let Some(event) = self.bridge.as_ref().and_then(|bridge| bridge.try_recv()) {
+            self.do_update(SessionMessage::Bridge(event));
+        }
+
+        if let Some(result) = self.autobaud.as_ref().and_then(|autobaud| autobaud.try_recv().ok()) {
+            self.do_update(SessionMessage::AutobaudFinished(result));
+        }
+
+        if let Some(result) = self.reconnect_attempt.as_ref().and_then(|attempt| attempt.try_recv().ok()) {
+            self.do_update(SessionMessage::ReconnectFinished(result));
+        }
+    }
+
+    fn transmit(&mut self, data: &[u8]) {
+        if !self.device_connected {
+            return;
+        }
+
+        if let Err(e) = self.serial.send(data) {
+            warn!("Couldn't send to {}: {e}", self.device());
+        }
+    }
+
+    fn transmit_addressed(&mut self, data: &[u8]) {
+        if !self.device_connected {
+            return;
+        }
+
+        if let Err(e) = self.serial.send_addressed(self.address, data) {
+            warn!("Couldn't send to address {:#04x} on {}: {e}", self.address, self.device());
+        }
+    }
+
+    /// Starts the next attempt once the interval is up, on a thread of its
+    /// own since opening a network port can take up to its connect timeout.
+    pub fn handle_reconnect(&mut self) {
+        if !self.reconnecting || self.reconnect_attempt.is_some() || self.last_reconnect_attempt.elapsed() < self.reconnect_interval {
+            return;
+        }
+
+        let (sender, receiver) = unbounded();
+        let serial = self.serial.clone();
+
+        thread::spawn(move || {
+            sender.send(serial.reconnect().map_err(|e| e.to_string())).ok();
+        });
+
+        self.reconnect_attempt = Some(receiver);
+    }
+}
@@ -4,28 +4,67 @@ use eframe::Frame;
 
 impl App {
     pub fn render_status_bar(&mut self, ctx: &Context, _frame: &mut Frame) {
+        let session = self.active_session();
+        let transmit = session.serial.transmit_status();
+
         TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 global_dark_light_mode_switch(ui);
-                ui.label(format!("{} {} | {}, {}{}{} flow control: {}           TX: {} | RX: {}      {}",
-                    self.current_serial_device,
-                    if self.device_connected {
+                ui.label(format!("{} {} | {}, {}{}{} flow control: {}{}           TX: {}{} | RX: {}{}      {}      {}      {}      {}",
+                    session.device(),
+                    if session.device_connected {
                         "OPENED"
+                    } else if session.is_reconnecting() {
+                        "RECONNECTING"
                     } else {
                         "CLOSED"
                     },
-                    self.serial_config.baudrate,
+                    session.serial_config.baudrate,
+
+                    format!("{:?}", session.serial_config.char_size).char_range(4..5),
+                    format!("{:?}", session.serial_config.parity).char_range(0..1),
+                    format!("{:?}", session.serial_config.stop_bits).char_range(0..1),
+                    format_args!("{:?}", session.serial_config.flow_control),
+                    match (session.serial_config.rs485.enabled, session.device_connected && session.serial.rs485_software_rts()) {
+                        (true, true) => " | RS-485 (software RTS)",
+                        (true, false) => " | RS-485",
+                        (false, _) => "",
+                    },
 
-                    format!("{:?}", self.serial_config.char_size).char_range(4..5),
-                    format!("{:?}", self.serial_config.parity).char_range(0..1),
-                    format!("{:?}", self.serial_config.stop_bits).char_range(0..1),
-                    format_args!("{:?}", self.serial_config.flow_control),
+                    transmit.written,
+                    if transmit.queued > 0 {
+                        format!(" (+{} queued)", transmit.queued)
+                    } else {
+                        String::new()
+                    },
+                    session.rx_cnt,
+                    if let Some(errors) = session.line_errors {
+                        format!(" | Errors: {} framing, {} parity, {} overrun, {} break",
+                            errors.framing, errors.parity, errors.overrun + errors.buffer_overrun, errors.breaks)
+                    } else {
+                        String::new()
+                    },
 
-                    self.tx_cnt,
-                    self.rx_cnt,
+                    if transmit.blocked {
+                        "TX blocked by flow control"
+                    } else {
+                        ""
+                    },
+
+                    if session.recording_started {
+                        format!("Logging to: {}", session.log_file_name)
+                    } else {
+                        String::new()
+                    },
+
+                    if let Some(bridge) = &session.bridge {
+                        format!("Bridge: {} ({} clients)", bridge.local_addr(), bridge.clients().len())
+                    } else {
+                        String::new()
+                    },
 
-                    if self.recording_started {
-                        format!("Logging to: {}", self.log_file_name)
+                    if let Some(pty) = &session.pty {
+                        format!("PTY: {}", pty.path())
                     } else {
                         String::new()
                     }
@@ -4,7 +4,9 @@ pub mod log_tab;
 
 use egui_dock::{TabViewer, Tree};
 use super::App;
-use eframe::egui::{self, Ui};
+use crate::Message;
+use crate::session::SessionId;
+use eframe::egui::{self, Id, Ui};
 
 use settings_tab::SettingsTab;
 use terminal_tab::TerminalTab;
@@ -12,11 +14,22 @@ use log_tab::LogTab;
 
 pub trait Tab {
     fn show_ui(&self, app: &mut App, ui: &mut Ui);
-    fn title(&self) -> &str;
+    fn title(&self, app: &App) -> String;
+    /// Stays the same when the title changes, so egui keeps the tab's state.
+    fn id(&self) -> Id;
+
+    /// Session shown by the tab. Tabs without one follow the focused session.
+    fn session(&self) -> Option<SessionId> {
+        None
+    }
+}
+
+pub fn terminal_tab(session: SessionId) -> Box<dyn Tab> {
+    Box::new(TerminalTab { session })
 }
 
-pub fn default_ui() -> Tree<Box<dyn Tab>> {
-    let mut tree: Tree<Box<dyn Tab>> = Tree::new(vec![Box::new(TerminalTab)]);
+pub fn default_ui(sessions: &[SessionId]) -> Tree<Box<dyn Tab>> {
+    let mut tree: Tree<Box<dyn Tab>> = Tree::new(sessions.iter().map(|&session| terminal_tab(session)).collect());
     let [main, side] = tree.split_right(0.into(), 0.75, vec![Box::new(SettingsTab)]);
     let [_side_top, _side_bottom] = tree.split_below(side, 0.3, vec![Box::new(LogTab)]);
     tree.set_focused_node(main);
@@ -31,6 +44,22 @@ impl TabViewer for App {
     }
 
     fn title(&mut self, tab: &mut Self::Tab) -> egui::WidgetText {
-        tab.title().into()
+        tab.title(self).into()
+    }
+
+    fn id(&mut self, tab: &mut Self::Tab) -> Id {
+        tab.id()
+    }
+
+    fn on_close(&mut self, tab: &mut Self::Tab) -> bool {
+        match tab.session() {
+            // There is always at least one session to show settings for.
+            Some(_) if self.sessions.len() == 1 => false,
+            Some(session) => {
+                self.do_update(Message::CloseSession(session));
+                true
+            },
+            None => true,
+        }
     }
 }
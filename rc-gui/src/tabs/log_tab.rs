@@ -1,6 +1,6 @@
 use super::Tab;
 
-use eframe::egui::{self, Frame, ScrollArea};
+use eframe::egui::{self, Frame, Id, ScrollArea};
 use crate::Message;
 
 pub struct LogTab;
@@ -27,7 +27,11 @@ impl Tab for LogTab {
             });
     }
 
-    fn title(&self) -> &str {
-        "Log"
+    fn title(&self, _app: &crate::App) -> String {
+        "Log".to_string()
+    }
+
+    fn id(&self) -> Id {
+        Id::new("log_tab")
     }
 }
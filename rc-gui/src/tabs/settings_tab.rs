@@ -1,105 +1,412 @@
-use eframe::egui::{ComboBox, Grid, TextBuffer, Ui};
-use serial2::{Parity, CharSize, FlowControl, StopBits, COMMON_BAUD_RATES};
+use std::time::Duration;
+use eframe::egui::{Button, Checkbox, ComboBox, DragValue, Grid, Id, SelectableLabel, TextBuffer, TextEdit, Ui};
+use serial2::{CharSize, FlowControl, StopBits, COMMON_BAUD_RATES};
+use rc_core::port_match::PortMatch;
+use rc_core::serial::{LineWait, Parity, PortInfo, Serial};
+use rc_core::transport::ConnectionType;
 use crate::Message;
+use crate::session::SessionMessage;
 use super::App;
 use super::Tab;
 
 pub struct SettingsTab;
 
 impl SettingsTab {
-    const PARITY: [Parity; 3] = [Parity::None, Parity::Even, Parity::Odd];
+    const PARITY: [Parity; 5] = [Parity::None, Parity::Even, Parity::Odd, Parity::Mark, Parity::Space];
     const CHAR_SIZE: [CharSize; 4] = [CharSize::Bits8, CharSize::Bits7, CharSize::Bits6, CharSize::Bits5];
     const STOP_BITS: [StopBits; 2] = [StopBits::One, StopBits::Two];
     const FLOW_CONTROL: [FlowControl; 3] = [FlowControl::None, FlowControl::RtsCts, FlowControl::XonXoff];
+    const AUTOBAUD_CANDIDATES: usize = 5;
+
+    fn port_details(port: &PortInfo) -> String {
+        let mut details = format!("Type: {}", port.port_type);
+
+        if let (Some(vid), Some(pid)) = (port.vid, port.pid) {
+            details.push_str(&format!("\nVID:PID: {vid:04x}:{pid:04x}"));
+        }
+
+        for (label, value) in [
+            ("Serial number", &port.serial_number),
+            ("Manufacturer", &port.manufacturer),
+            ("Product", &port.product),
+        ] {
+            if let Some(value) = value {
+                details.push_str(&format!("\n{label}: {value}"));
+            }
+        }
+
+        details
+    }
+
+    fn delay_ms(ui: &mut Ui, delay: &mut Duration) {
+        let mut delay_ms = delay.as_millis() as u64;
+
+        if ui.add(DragValue::new(&mut delay_ms).clamp_range(0..=10_000)).changed() {
+            *delay = Duration::from_millis(delay_ms);
+        }
+    }
 }
 
 impl Tab for SettingsTab {
     fn show_ui(&self, app: &mut App, ui: &mut Ui) {
-        ui.set_enabled(!app.device_connected);
+        let serial_devices = app.serial_devices.clone();
+        let port_holders = app.port_holders.clone();
+        let mut refresh = false;
+        let session = app.active_session_mut();
+
+        let reconfigurable = session.serial.capabilities().is_some_and(|capabilities| capabilities.configure);
+        let connection_enabled = !session.device_connected && !session.is_reconnecting();
+        let line_enabled = connection_enabled || reconfigurable;
+        let config = session.serial_config.clone();
 
         Grid::new("settings_tab_grid").show(ui, |ui| {
-            ui.label("Device");
-            ComboBox::from_id_source("device")
-            .selected_text(app.current_serial_device.clone())
-            .show_ui(ui, |ui| {
-                for device in &app.serial_devices {
-                    ui.selectable_value(&mut app.current_serial_device, device.clone(), device.clone());
-                }
+            ui.label("Terminal");
+            ui.label(session.title());
+            ui.end_row();
+
+            ui.label("Connection");
+            ui.add_enabled_ui(connection_enabled, |ui| {
+                ComboBox::from_id_source("connection_type")
+                    .selected_text(session.connection_type.to_string())
+                    .show_ui(ui, |ui| {
+                        for connection_type in ConnectionType::ALL {
+                            ui.selectable_value(&mut session.connection_type, connection_type, connection_type.to_string());
+                        }
+                    });
             });
-            if ui.button("Refresh").clicked() {
-                app.do_update(Message::RefreshSerialDevices);
-            }
             ui.end_row();
 
-            ui.label("Baud Rate");
-            ComboBox::from_id_source("baudrate")
-                .selected_text(format!("{}", app.serial_config.baudrate as i32))
-                .show_ui(ui, |ui| {
-                    for baudrate in COMMON_BAUD_RATES {
-                        ui.selectable_value(&mut app.serial_config.baudrate, *baudrate, baudrate.to_string());
+            if session.connection_type == ConnectionType::Serial {
+                ui.label("Device");
+                ui.add_enabled_ui(connection_enabled, |ui| {
+                    let selected = serial_devices.iter().find(|port| port.name == session.current_serial_device).map(Self::port_details);
+
+                    let combo_box = ComboBox::from_id_source("device")
+                    .selected_text(session.current_serial_device.clone())
+                    .show_ui(ui, |ui| {
+                        for port in &serial_devices {
+                            let description = port.description();
+                            let label = if description.is_empty() {
+                                port.name.clone()
+                            } else {
+                                format!("{}  {description}", port.name)
+                            };
+
+                            let holder = port_holders.iter().find(|(name, _)| *name == port.name).map(|(_, holder)| holder);
+
+                            // Ports of this instance are found too. They stay selectable,
+                            // since only connecting tells whether its other tab locked them.
+                            let (label, holder) = match holder {
+                                Some(holder) if holder.pid == std::process::id() => (format!("{label}  (open in another tab)"), None),
+                                holder => (label, holder),
+                            };
+                            let selected = session.current_serial_device == port.name;
+
+                            let response = ui.add_enabled(holder.is_none(), SelectableLabel::new(selected, label))
+                                .on_hover_text(Self::port_details(port));
+
+                            if let Some(holder) = holder {
+                                response.on_disabled_hover_text(format!("{}\nIn use by {holder}", Self::port_details(port)));
+                            } else if response.clicked() {
+                                session.current_serial_device = port.name.clone();
+                            }
+                        }
+                    });
+
+                    if let Some(details) = selected {
+                        combo_box.response.on_hover_text(details);
                     }
                 });
+                refresh = ui.add_enabled(connection_enabled, Button::new("Refresh")).clicked();
+                ui.end_row();
+
+                // Typed rather than picked: paths that aren't listed, such as
+                // /dev/serial/by-id/..., and rules that follow an adapter around.
+                ui.label("Path or Rule");
+                let rule = TextEdit::singleline(&mut session.current_serial_device).hint_text("usb:0403:6001");
+                let response = ui.add_enabled(connection_enabled, rule);
+
+                let hint = match PortMatch::parse(&session.current_serial_device) {
+                    None => "A device path, or a rule such as usb:VID[:PID], serial:NUMBER or product:\"GLOB\"".to_string(),
+                    Some(Err(e)) => e,
+                    Some(Ok(rule)) => match rule.resolve(&serial_devices) {
+                        Ok(name) => format!("Matches {name}"),
+                        Err(e) => e,
+                    },
+                };
+
+                response.on_hover_text(hint);
+            } else {
+                ui.label(match session.connection_type {
+                    ConnectionType::Loopback => "Options",
+                    ConnectionType::Mock => "Script",
+                    _ => "Address",
+                });
+                ui.add_enabled(
+                    connection_enabled,
+                    TextEdit::singleline(&mut session.device_address).hint_text(session.connection_type.address_hint()),
+                );
+            }
+            ui.end_row();
+
+            ui.label("Baud Rate");
+            ui.add_enabled_ui(line_enabled, |ui| {
+                ComboBox::from_id_source("baudrate")
+                    .selected_text(format!("{}", session.serial_config.baudrate as i32))
+                    .show_ui(ui, |ui| {
+                        for baudrate in COMMON_BAUD_RATES {
+                            ui.selectable_value(&mut session.serial_config.baudrate, *baudrate, baudrate.to_string());
+                        }
+                    });
+            });
+            ui.add_enabled(line_enabled, DragValue::new(&mut session.serial_config.baudrate).speed(0.0).clamp_range(1..=u32::MAX))
+                .on_hover_text("Click to enter any rate the driver supports");
+            if ui.add_enabled(connection_enabled && !session.is_detecting_baudrate(), Button::new("Detect")).clicked() {
+                session.do_update(SessionMessage::StartAutobaud);
+            }
             ui.end_row();
 
             ui.label("Char bits");
-            ComboBox::from_id_source("char_bits")
-                .selected_text(format!("{:?}", app.serial_config.char_size).char_range(4..5))
-                .show_ui(ui, |ui| {
-                    for char_size in Self::CHAR_SIZE {
-                        ui.selectable_value(
-                            &mut app.serial_config.char_size,
-                            char_size,
-                            format!("{char_size:?}").char_range(4..5),
-                        );
-                    }
-                });
+            ui.add_enabled_ui(line_enabled, |ui| {
+                ComboBox::from_id_source("char_bits")
+                    .selected_text(format!("{:?}", session.serial_config.char_size).char_range(4..5))
+                    .show_ui(ui, |ui| {
+                        for char_size in Self::CHAR_SIZE {
+                            ui.selectable_value(
+                                &mut session.serial_config.char_size,
+                                char_size,
+                                format!("{char_size:?}").char_range(4..5),
+                            );
+                        }
+                    });
+            });
             ui.end_row();
 
             ui.label("Stop Bits");
-            ComboBox::from_id_source("stop_bits")
-                .selected_text(format!("{:?}", app.serial_config.stop_bits))
-                .show_ui(ui, |ui| {
-                    for stop_bits in Self::STOP_BITS {
-                        ui.selectable_value(
-                            &mut app.serial_config.stop_bits,
-                            stop_bits,
-                            format!("{stop_bits:?}"),
-                        );
-                    }
-                });
+            ui.add_enabled_ui(line_enabled, |ui| {
+                ComboBox::from_id_source("stop_bits")
+                    .selected_text(format!("{:?}", session.serial_config.stop_bits))
+                    .show_ui(ui, |ui| {
+                        for stop_bits in Self::STOP_BITS {
+                            ui.selectable_value(
+                                &mut session.serial_config.stop_bits,
+                                stop_bits,
+                                format!("{stop_bits:?}"),
+                            );
+                        }
+                    });
+            });
             ui.end_row();
 
             ui.label("Parity");
-            ComboBox::from_id_source("parity")
-                .selected_text(format!("{:?}", app.serial_config.parity))
-                .show_ui(ui, |ui| {
-                    for parity in Self::PARITY {
-                        ui.selectable_value(
-                            &mut app.serial_config.parity,
-                            parity,
-                            format!("{parity:?}"),
-                        );
-                    }
-                });
+            ui.add_enabled_ui(line_enabled, |ui| {
+                ComboBox::from_id_source("parity")
+                    .selected_text(format!("{:?}", session.serial_config.parity))
+                    .show_ui(ui, |ui| {
+                        for parity in Self::PARITY {
+                            ui.selectable_value(
+                                &mut session.serial_config.parity,
+                                parity,
+                                format!("{parity:?}"),
+                            );
+                        }
+                    });
+            });
             ui.end_row();
 
             ui.label("Flow control");
-            ComboBox::from_id_source("flow_control")
-                .selected_text(format!("{:?}", app.serial_config.flow_control))
+            ui.add_enabled_ui(line_enabled, |ui| {
+                ComboBox::from_id_source("flow_control")
+                    .selected_text(format!("{:?}", session.serial_config.flow_control))
+                    .show_ui(ui, |ui| {
+                        for flow_control in Self::FLOW_CONTROL {
+                            ui.selectable_value(
+                                &mut session.serial_config.flow_control,
+                                flow_control,
+                                format!("{flow_control:?}"),
+                            );
+                        }
+                    });
+            });
+            ui.end_row();
+
+            ui.label("Mark errors");
+            ui.add_enabled_ui(line_enabled, |ui| {
+                ui.checkbox(&mut session.serial_config.mark_errors, "")
+                    .on_hover_text("Show bytes received with parity or framing errors as \u{FFFD}");
+            });
+            ui.end_row();
+
+            ui.label("RS-485");
+            ui.add_enabled_ui(line_enabled, |ui| {
+                ui.checkbox(&mut session.serial_config.rs485.enabled, "")
+                    .on_hover_text("Half-duplex: RTS switches the transceiver to sending");
+            });
+            ui.end_row();
+
+            if session.serial_config.rs485.enabled {
+                let rs485 = &mut session.serial_config.rs485;
+
+                ui.label("RTS on send");
+                ComboBox::from_id_source("rs485_rts_on_send")
+                    .selected_text(if rs485.rts_on_send { "High" } else { "Low" })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut rs485.rts_on_send, true, "High");
+                        ui.selectable_value(&mut rs485.rts_on_send, false, "Low");
+                    });
+                ui.end_row();
+
+                ui.label("Delay before send (ms)");
+                Self::delay_ms(ui, &mut rs485.delay_before_send);
+                ui.end_row();
+
+                ui.label("Delay after send (ms)");
+                Self::delay_ms(ui, &mut rs485.delay_after_send);
+                ui.end_row();
+
+                ui.label("Receive while sending");
+                ui.checkbox(&mut rs485.rx_during_tx, "");
+                ui.end_row();
+
+                ui.label("Remove echo");
+                ui.checkbox(&mut rs485.remove_echo, "")
+                    .on_hover_text("Hide our own data when the transceiver echoes it");
+                ui.end_row();
+            }
+
+            ui.label("Exclusive");
+            ui.add_enabled(connection_enabled, Checkbox::new(&mut session.serial_config.exclusive, ""))
+                .on_hover_text("Lock the port so other programs can't open it at the same time");
+            ui.end_row();
+
+            ui.label("Reconnect");
+            ui.checkbox(&mut session.auto_reconnect, "")
+                .on_hover_text("Reopen the port with the same settings when it disappears and comes back");
+            ui.end_row();
+
+            ui.label("Break (ms)");
+            ui.add(DragValue::new(&mut session.break_duration_ms).clamp_range(1..=10_000));
+            ui.end_row();
+
+            ui.label("Char delay (ms)");
+            Self::delay_ms(ui, &mut session.serial_config.pacing.char_delay);
+            ui.end_row();
+
+            ui.label("Line delay (ms)");
+            Self::delay_ms(ui, &mut session.serial_config.pacing.line_delay);
+            ui.end_row();
+
+            ui.label("Wait for");
+            let line_wait = &mut session.serial_config.pacing.line_wait;
+            ComboBox::from_id_source("line_wait")
+                .selected_text(match line_wait {
+                    LineWait::Nothing => "Nothing",
+                    LineWait::Echo => "Echo",
+                    LineWait::Prompt(_) => "Prompt",
+                })
                 .show_ui(ui, |ui| {
-                    for flow_control in Self::FLOW_CONTROL {
-                        ui.selectable_value(
-                            &mut app.serial_config.flow_control,
-                            flow_control,
-                            format!("{flow_control:?}"),
-                        );
+                    ui.selectable_value(line_wait, LineWait::Nothing, "Nothing");
+                    ui.selectable_value(line_wait, LineWait::Echo, "Echo")
+                        .on_hover_text("Wait for each line to come back before sending the next");
+                    if ui.selectable_label(matches!(line_wait, LineWait::Prompt(_)), "Prompt").clicked()
+                        && !matches!(line_wait, LineWait::Prompt(_)) {
+                        *line_wait = LineWait::Prompt(String::from("> "));
                     }
                 });
+            if let LineWait::Prompt(prompt) = line_wait {
+                ui.add(TextEdit::singleline(prompt).desired_width(60.0))
+                    .on_hover_text("Text the device prints when it is ready for the next line");
+            }
             ui.end_row();
         });
+
+        if session.is_detecting_baudrate() {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Detecting baud rate...");
+            });
+        } else if !session.autobaud_candidates.is_empty() {
+            Grid::new("autobaud_grid").show(ui, |ui| {
+                for candidate in session.autobaud_candidates.iter().take(Self::AUTOBAUD_CANDIDATES) {
+                    ui.label(candidate.config.baudrate.to_string());
+                    ui.label(candidate.config.framing());
+                    ui.label(format!("{:.2}", candidate.score))
+                        .on_hover_text(String::from_utf8_lossy(&candidate.sample));
+                    if ui.add_enabled(connection_enabled, Button::new("Use")).clicked() {
+                        session.serial_config.baudrate = candidate.config.baudrate;
+                        session.serial_config.char_size = candidate.config.char_size;
+                        session.serial_config.parity = candidate.config.parity;
+                    }
+                    ui.end_row();
+                }
+            });
+        }
+
+        if session.device_connected && session.serial_config.pacing != config.pacing {
+            session.serial.set_pacing(session.serial_config.pacing.clone());
+        }
+
+        if session.device_connected
+            && (!session.serial_config.mismatches(&config).is_empty() || session.serial_config.mark_errors != config.mark_errors
+                || session.serial_config.rs485 != config.rs485) {
+            session.do_update(SessionMessage::ApplySerialConfig);
+        }
+
+        ui.separator();
+
+        Grid::new("bridge_grid").show(ui, |ui| {
+            let bridge_stopped = session.bridge.is_none();
+
+            ui.label("Bridge");
+            ui.add_enabled(bridge_stopped, TextEdit::singleline(&mut session.bridge_address).hint_text("host:port"));
+            ui.add_enabled(bridge_stopped, Checkbox::new(&mut session.bridge_rfc2217, "RFC 2217"));
+            ui.end_row();
+
+            ui.label("");
+            if bridge_stopped {
+                if ui.add_enabled(session.device_connected, Button::new("Start bridge")).clicked() {
+                    session.do_update(SessionMessage::StartBridge);
+                }
+            } else if ui.button("Stop bridge").clicked() {
+                session.do_update(SessionMessage::StopBridge);
+            }
+            ui.end_row();
+
+            if let Some(bridge) = &session.bridge {
+                for client in bridge.clients() {
+                    ui.label("");
+                    ui.label(client.to_string());
+                    ui.end_row();
+                }
+            }
+
+            ui.label("Virtual port");
+            if session.pty.is_none() {
+                if ui.add_enabled(session.device_connected, Button::new("Create PTY")).clicked() {
+                    session.do_update(SessionMessage::OpenPty);
+                }
+            } else if ui.button("Close PTY").clicked() {
+                session.do_update(SessionMessage::ClosePty);
+            }
+            ui.end_row();
+
+            for path in Serial::virtual_ports() {
+                ui.label("");
+                ui.label(path).on_hover_text("Open this path from another program");
+                ui.end_row();
+            }
+        });
+
+        if refresh {
+            app.do_update(Message::RefreshSerialDevices);
+        }
+    }
+
+    fn title(&self, _app: &App) -> String {
+        "Settings".to_string()
     }
 
-    fn title(&self) -> &str {
-        "Settings"
+    fn id(&self) -> Id {
+        Id::new("settings_tab")
     }
 }
@@ -1,69 +1,111 @@
-use eframe::egui::{self, Ui};
-use egui::{ScrollArea, TextEdit, Layout, Align};
-use crate::Message;
+use eframe::egui::{self, Id, Ui};
+use egui::{Button, DragValue, ScrollArea, TextEdit, Layout, Align};
+use crate::session::{SessionId, SessionMessage};
 use crate::widgets::line_end_picker::LineEndPicker;
+use crate::widgets::led::Led;
 // use crate::widgets::file_protocol_picker::FileProtocolPicker;
 use super::App;
 use super::Tab;
 
-pub struct TerminalTab;
+pub struct TerminalTab {
+    pub session: SessionId,
+}
 
 impl Tab for TerminalTab {
     fn show_ui(&self, app: &mut App, ui: &mut Ui) {
+        let Some(session) = app.session_mut(self.session) else {
+            return;
+        };
+
         ui.horizontal(|ui| {
-            if app.device_connected {
+            if session.device_connected || session.is_reconnecting() {
                 if ui.button("Disconnect").clicked() {
-                    app.do_update(Message::Disconnect);
+                    session.do_update(SessionMessage::Disconnect);
                 }
             } else if ui.button("Connect").clicked() {
-                app.do_update(Message::Connect);
+                session.do_update(SessionMessage::Connect);
             }
 
-            if !app.recording_started {
+            if !session.recording_started {
                 if ui.button("Record").clicked() {
-                    app.do_update(Message::StartRecording);
+                    session.do_update(SessionMessage::StartRecording);
                 }
             } else if ui.button("Stop recording").clicked() {
-                app.do_update(Message::StopRecording);
+                session.do_update(SessionMessage::StopRecording);
             }
 
             if ui.button("Clear").clicked() {
-                app.do_update(Message::ClearTerminalText);
+                session.do_update(SessionMessage::ClearTerminalText);
+            }
+
+            let send_break = session.device_connected && session.serial.capabilities().is_some_and(|capabilities| capabilities.send_break);
+            if ui.add_enabled(send_break, Button::new("Break")).on_hover_text(format!("Send BREAK for {} ms", session.break_duration_ms)).clicked() {
+                session.do_update(SessionMessage::SendBreak);
             }
 
-            ui.checkbox(&mut app.timestamp, "Time").on_hover_text("Show time in receive box");
-            ui.checkbox(&mut app.lock_scrolling, "Lock scrolling");
+            ui.checkbox(&mut session.timestamp, "Time").on_hover_text("Show time in receive box");
+            ui.checkbox(&mut session.lock_scrolling, "Lock scrolling");
+
+            ui.separator();
+
+            let modem_lines = session.device_connected && session.serial.capabilities().is_some_and(|capabilities| capabilities.modem_lines);
+            ui.add_enabled_ui(modem_lines, |ui| {
+                if ui.selectable_label(session.dtr, "DTR").on_hover_text("Data Terminal Ready").clicked() {
+                    session.do_update(SessionMessage::SetDtr(!session.dtr));
+                }
+
+                if ui.selectable_label(session.rts, "RTS").on_hover_text("Request To Send").clicked() {
+                    session.do_update(SessionMessage::SetRts(!session.rts));
+                }
+
+                ui.add(Led::new("CTS", session.modem_status.cts));
+                ui.add(Led::new("DSR", session.modem_status.dsr));
+                ui.add(Led::new("RI", session.modem_status.ri));
+                ui.add(Led::new("CD", session.modem_status.cd));
+            });
         });
 
         ui.vertical(|ui| {
             ui.with_layout(Layout::bottom_up(egui::Align::Center), |ui| {
                 ui.horizontal(|ui| {
                     ui.with_layout(Layout::right_to_left(egui::Align::Max), |ui| {
-                        // ui.add(FileProtocolPicker::new(80f32, &mut app.file_protocol));
+                        // ui.add(FileProtocolPicker::new(80f32, &mut session.file_protocol));
 
                         // if ui.button("Send file...").clicked() {
 
                         // }
 
-                        ui.add(LineEndPicker::new(70f32, &mut app.line_end));
+                        ui.add(LineEndPicker::new(70f32, &mut session.line_end));
+
+                        ui.add_enabled(session.address_mode, DragValue::new(&mut session.address).hexadecimal(2, false, true).prefix("0x"));
+                        ui.checkbox(&mut session.address_mode, "Address")
+                            .on_hover_text("Send an address byte with the 9th bit set before each line, using mark and space parity");
 
-                        ui.add_sized(ui.available_size(), TextEdit::singleline(&mut app.transmit_text));
+                        ui.add_sized(ui.available_size(), TextEdit::singleline(&mut session.transmit_text));
                     });
                 });
 
                 ScrollArea::vertical()
                     .auto_shrink([false, false])
-                    .stick_to_bottom(app.lock_scrolling)
+                    .stick_to_bottom(session.lock_scrolling)
                     .show(ui, |ui| {
                         ui.with_layout(Layout::left_to_right(Align::Center).with_cross_justify(true), |ui| {
-                            ui.add_sized(ui.available_size(), TextEdit::multiline(&mut app.terminal_text).interactive(false))
+                            ui.add_sized(ui.available_size(), TextEdit::multiline(&mut session.terminal_text).interactive(false))
                         });
                     });
             });
         });
     }
 
-    fn title(&self) -> &str {
-        "Terminal"
+    fn title(&self, app: &App) -> String {
+        app.session(self.session).map(|session| session.title()).unwrap_or_default()
+    }
+
+    fn id(&self) -> Id {
+        Id::new(("terminal_tab", self.session))
+    }
+
+    fn session(&self) -> Option<SessionId> {
+        Some(self.session)
     }
 }
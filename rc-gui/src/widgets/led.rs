@@ -0,0 +1,35 @@
+use eframe::egui::{self, Color32, Response, Sense, Widget};
+
+pub struct Led<'a> {
+    label: &'a str,
+    on: bool,
+}
+
+impl<'a> Led<'a> {
+    const RADIUS: f32 = 5.0;
+
+    pub fn new(label: &'a str, on: bool) -> Self {
+        Self {
+            label,
+            on,
+        }
+    }
+}
+
+impl<'a> Widget for Led<'a> {
+    fn ui(self, ui: &mut egui::Ui) -> Response {
+        ui.horizontal(|ui| {
+            let (rect, response) = ui.allocate_exact_size(egui::Vec2::splat(Self::RADIUS * 2.0), Sense::hover());
+            let color = if self.on {
+                Color32::from_rgb(0, 200, 0)
+            } else {
+                ui.style().visuals.widgets.inactive.bg_fill
+            };
+
+            ui.painter().circle_filled(rect.center(), Self::RADIUS, color);
+            ui.label(self.label);
+            response
+        })
+        .inner
+    }
+}
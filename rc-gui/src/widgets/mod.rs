@@ -1,2 +1,3 @@
 pub mod line_end_picker;
-pub mod file_protocol_picker;
\ No newline at end of file
+pub mod file_protocol_picker;
+pub mod led;
\ No newline at end of file
@@ -5,6 +5,9 @@ fn main() -> Result<()> {
     match cli::run()? {
         (cli::AppType::Tui, device, config) => rc_tui::run(device, config)?,
         (cli::AppType::Gui, device, config) => rc_gui::run(device, config)?,
+        (cli::AppType::Bridge { listen, rfc2217 }, device, config) => rc_core::bridge::run(&device, config, &listen, rfc2217)?,
+        (cli::AppType::Autobaud(options), device, config) => rc_core::autobaud::run(&device, config, options)?,
+        (cli::AppType::List { json }, _, _) => cli::list_ports(json)?,
     }
 
     Ok(())